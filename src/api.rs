@@ -0,0 +1,188 @@
+//! Versioned JSON API, responses and errors are JSON.
+
+use axum::{
+    extract::{Host, Path},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use hyper::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::io;
+
+use crate::{
+    handler::{self, NewPaste, UploadOptions},
+    utils::{ClientIp, Protocol, UploadBody},
+    ConfigExtension, Error, Expiry, Language, PasteId, PasteMetadata, StorageExtension,
+};
+
+/// [`Error`] serialized as `{"code": ..., "message": ...}`.
+#[derive(Debug)]
+pub struct ApiError(Error);
+
+impl From<Error> for ApiError {
+    fn from(err: Error) -> Self {
+        Self(err)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = Json(serde_json::json!({
+            "code": self.0.code(),
+            "message": self.0.to_string(),
+        }));
+        (self.0.status_code(), body).into_response()
+    }
+}
+
+pub type Result<T, E = ApiError> = std::result::Result<T, E>;
+
+/// Text paste created from JSON.
+#[derive(Debug, Deserialize)]
+pub struct CreatePaste {
+    content: String,
+    file_name: Option<String>,
+    /// Extension used to highlight the paste, e.g. `rs`.
+    lang: Option<String>,
+    expires: Option<Expiry>,
+    #[serde(default)]
+    burn: bool,
+}
+
+/// A paste as returned by the API.
+#[derive(Debug, Serialize)]
+pub struct PasteInfo {
+    id: String,
+    url: String,
+    raw_url: String,
+    /// Only returned when the paste is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delete_token: Option<String>,
+    file_name: Option<String>,
+    content_type: Option<String>,
+    language: Option<&'static str>,
+    size: u64,
+    created_at: u64,
+    expires_at: Option<u64>,
+    burn_after_reading: bool,
+    /// Files of a paste holding several files.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    files: Vec<FileInfo>,
+}
+
+/// A file of a paste holding several files.
+#[derive(Debug, Serialize)]
+pub struct FileInfo {
+    name: String,
+    url: String,
+    content_type: String,
+    language: Option<&'static str>,
+    size: u64,
+}
+
+fn language(ext: Option<&str>) -> Option<&'static str> {
+    ext.and_then(Language::from_extension)
+        .map(|language| language.as_str())
+}
+
+impl PasteInfo {
+    fn new(base: &str, id: &PasteId, metadata: PasteMetadata) -> Self {
+        let path = match &metadata.extension {
+            Some(ext) => format!("{id}.{ext}"),
+            None => id.to_string(),
+        };
+        let files = metadata
+            .files
+            .into_iter()
+            .map(|file| FileInfo {
+                url: format!("{base}/{id}/{}", file.name),
+                language: language(file.extension.as_deref()),
+                name: file.name,
+                content_type: file.content_type,
+                size: file.size,
+            })
+            .collect();
+
+        Self {
+            id: id.to_string(),
+            url: format!("{base}/{path}"),
+            raw_url: format!("{base}/{id}/raw"),
+            delete_token: None,
+            file_name: metadata.file_name,
+            content_type: metadata.content_type,
+            language: language(metadata.extension.as_deref()),
+            size: metadata.size,
+            created_at: metadata.created_at,
+            expires_at: metadata.expires_at,
+            burn_after_reading: metadata.burn_after_reading,
+            files,
+        }
+    }
+}
+
+/// Creates a paste from JSON or, like [`handler::upload`], from a form or the request body.
+pub async fn create_paste(
+    Protocol(protocol): Protocol,
+    Host(host): Host,
+    ClientIp(uploader): ClientIp,
+    body: std::result::Result<UploadBody, Error>,
+    Extension(storage): StorageExtension,
+    Extension(config): ConfigExtension,
+) -> Result<impl IntoResponse> {
+    let mut options = UploadOptions::default();
+
+    let paste = match body? {
+        UploadBody::Json(body) => {
+            // leaves room for escaping the content
+            let limit = config.max_text_size.saturating_mul(2);
+            let data = handler::read_body(body, limit).await?;
+            let request: CreatePaste =
+                serde_json::from_slice(&data).map_err(|_| Error::BadRequest)?;
+
+            options.expires = request.expires;
+            options.burn = request.burn;
+
+            let content = io::Cursor::new(request.content.into_bytes());
+            let upload = handler::upload_reader(content, &options, &config)
+                .await?
+                .ok_or(Error::Empty)?;
+            NewPaste {
+                file_name: request.file_name,
+                lang: request
+                    .lang
+                    .as_deref()
+                    .and_then(handler::sanitize_extension),
+                upload,
+                rest: None,
+            }
+        }
+        body => handler::read_paste(body, None, &mut options, &config).await?,
+    };
+
+    let (id, metadata) =
+        handler::save_paste(&*storage, &config, uploader, &options, paste, None).await?;
+
+    let delete_token = metadata.delete_token.clone();
+    let mut info = PasteInfo::new(&format!("{protocol}://{host}"), &id, metadata);
+    info.delete_token = delete_token;
+
+    let location = [(header::LOCATION, info.url.clone())];
+    Ok((StatusCode::CREATED, location, Json(info)))
+}
+
+/// Returns the metadata of a paste, never burns it.
+pub async fn get_paste(
+    Path(id): Path<String>,
+    Protocol(protocol): Protocol,
+    Host(host): Host,
+    Extension(storage): StorageExtension,
+) -> Result<Json<PasteInfo>> {
+    let id = PasteId::new(id).map_err(|_| Error::NotFound)?;
+    let metadata = storage.metadata(&id).await.map_err(handler::load_error)?;
+
+    Ok(Json(PasteInfo::new(
+        &format!("{protocol}://{host}"),
+        &id,
+        metadata,
+    )))
+}
@@ -0,0 +1,121 @@
+//! Archives of the files of a paste.
+
+const BLOCK_LEN: usize = 512;
+
+/// Packs files into an uncompressed ustar archive, all files are modified at `mtime`.
+///
+/// Returns `None` if a path is too long for a header, see [`split_path`].
+pub(crate) fn tar<'a>(
+    files: impl IntoIterator<Item = (String, &'a [u8])>,
+    mtime: u64,
+) -> Option<Vec<u8>> {
+    let mut archive = Vec::new();
+
+    for (path, data) in files {
+        archive.extend_from_slice(&header(&path, data.len() as u64, mtime)?);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len().next_multiple_of(BLOCK_LEN), 0);
+    }
+
+    // the end of the archive is marked by two empty blocks
+    archive.resize(archive.len() + 2 * BLOCK_LEN, 0);
+    Some(archive)
+}
+
+/// Splits `path` into the prefix and name fields of a header, which hold up to 155 and
+/// 100 bytes and are joined by a `/`.
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+
+    let (index, _) = path
+        .match_indices('/')
+        .find(|&(index, _)| index <= 155 && path.len() - index - 1 <= 100)?;
+    Some((&path[..index], &path[index + 1..]))
+}
+
+fn header(path: &str, size: u64, mtime: u64) -> Option<[u8; BLOCK_LEN]> {
+    let mut header = [0; BLOCK_LEN];
+
+    let (prefix, name) = split_path(path)?;
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut header[100..108], 0o644);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(&mut header[124..136], size);
+    octal(&mut header[136..148], mtime);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    // the checksum is calculated with its own field set to spaces
+    header[148..156].fill(b' ');
+    let checksum = header.iter().map(|&b| u64::from(b)).sum();
+    octal(&mut header[148..155], checksum);
+
+    Some(header)
+}
+
+/// Writes `value` as zero padded octal digits terminated by NUL.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{value:0width$o}", width = field.len() - 1);
+    let digits = &digits.as_bytes()[digits.len() - (field.len() - 1)..];
+    field[..digits.len()].copy_from_slice(digits);
+    field[digits.len()] = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn unpack(archive: &[u8]) -> Vec<(String, u64, Vec<u8>)> {
+        tar::Archive::new(archive)
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().to_string_lossy().into_owned();
+                let mtime = entry.header().mtime().unwrap();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                (path, mtime, data)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn read_back() {
+        let large = vec![b'x'; 1000];
+        let files = [
+            ("abc/main.rs".to_owned(), &b"fn main() {}\n"[..]),
+            ("abc/empty.txt".to_owned(), &b""[..]),
+            ("abc/large.txt".to_owned(), &large[..]),
+        ];
+        let archive = tar(files.clone(), 1_700_000_000).unwrap();
+        assert_eq!(archive.len() % BLOCK_LEN, 0);
+
+        let entries = unpack(&archive);
+        assert_eq!(entries.len(), files.len());
+        for ((path, mtime, data), (expected_path, expected_data)) in entries.iter().zip(&files) {
+            assert_eq!(path, expected_path);
+            assert_eq!(*mtime, 1_700_000_000);
+            assert_eq!(data, expected_data);
+        }
+    }
+
+    #[test]
+    fn long_paths() {
+        let name = format!("{}.txt", "n".repeat(96));
+        let path = format!("{}/{name}", "d".repeat(155));
+        let archive = tar([(path.clone(), &b"long"[..])], 0).unwrap();
+        assert_eq!(unpack(&archive), [(path, 0, b"long".to_vec())]);
+
+        // neither fits
+        let path = format!("{}/{}", "d".repeat(156), "n".repeat(100));
+        assert!(tar([(path, &b""[..])], 0).is_none());
+        assert!(tar([("n".repeat(101), &b""[..])], 0).is_none());
+    }
+}
@@ -0,0 +1,131 @@
+use axum::Extension;
+use std::{net::IpAddr, str::FromStr, sync::Arc};
+
+use crate::{ConfigExtension, Expiry};
+
+/// Server-wide settings applied to uploads.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Expiry used when an upload does not request one.
+    pub default_expiry: Expiry,
+    /// Upper bound for any requested expiry.
+    pub max_expiry: Expiry,
+    /// Maximum size of a text paste in bytes.
+    pub max_text_size: u64,
+    /// Maximum size of an image paste in bytes.
+    pub max_image_size: u64,
+    /// Reverse proxies whose `Forwarded`, `X-Forwarded-For` and `X-Forwarded-Proto` headers
+    /// are honored, the address and scheme of all other clients are taken from the connection.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Config {
+    /// Default of both size limits.
+    pub const DEFAULT_MAX_SIZE: u64 = 10 * 1000 * 1024;
+
+    /// Resolves the effective expiry of an upload, capped at `max_expiry`.
+    pub fn expiry(&self, requested: Option<Expiry>) -> Expiry {
+        requested
+            .unwrap_or(self.default_expiry)
+            .min(self.max_expiry)
+    }
+
+    pub fn into_extension(self) -> ConfigExtension {
+        Extension(Arc::new(self))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            default_expiry: Expiry::default(),
+            max_expiry: Expiry::default(),
+            max_text_size: Self::DEFAULT_MAX_SIZE,
+            max_image_size: Self::DEFAULT_MAX_SIZE,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+/// An amount of bytes, parsed from strings like `1024`, `512KiB`, `10MB` or `1GiB`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+#[derive(thiserror::Error, Debug)]
+#[error("invalid size '{0}', expected e.g. '512KiB' or '10MB'")]
+pub struct ParseByteSizeError(String);
+
+impl FromStr for ByteSize {
+    type Err = ParseByteSizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (value, unit) = s.split_at(split);
+
+        let value: u64 = value
+            .parse()
+            .map_err(|_| ParseByteSizeError(s.to_owned()))?;
+        let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kb" => 1000,
+            "kib" => 1024,
+            "m" | "mb" => 1000 * 1000,
+            "mib" => 1024 * 1024,
+            "g" | "gb" => 1000 * 1000 * 1000,
+            "gib" => 1024 * 1024 * 1024,
+            _ => return Err(ParseByteSizeError(s.to_owned())),
+        };
+
+        value
+            .checked_mul(multiplier)
+            .map(Self)
+            .ok_or_else(|| ParseByteSizeError(s.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(s: &str) -> Option<u64> {
+        s.parse::<ByteSize>().ok().map(|size| size.0)
+    }
+
+    #[test]
+    fn byte_sizes() {
+        assert_eq!(size("1024"), Some(1024));
+        assert_eq!(size("0"), Some(0));
+        assert_eq!(size("100B"), Some(100));
+        assert_eq!(size(" 10 MB "), Some(10_000_000));
+        assert_eq!(size("1gib"), Some(1 << 30));
+    }
+
+    #[test]
+    fn binary_and_decimal_units() {
+        assert_eq!(size("512KiB"), Some(512 * 1024));
+        assert_eq!(size("512KB"), Some(512 * 1000));
+        assert_eq!(size("512k"), Some(512 * 1000));
+        assert_eq!(size("10MiB"), Some(10 * 1024 * 1024));
+        assert_eq!(size("10MB"), Some(10 * 1000 * 1000));
+        assert_eq!(size("2GiB"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(size("2GB"), Some(2 * 1000 * 1000 * 1000));
+    }
+
+    #[test]
+    fn invalid_byte_sizes() {
+        for s in ["", "MB", "1.5MB", "-1", "10TB", "10 M B", "0x10"] {
+            assert_eq!(size(s), None, "{s:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn byte_size_overflow() {
+        assert_eq!(size("18446744073709551615"), Some(u64::MAX));
+        assert_eq!(size("18446744073709551616"), None);
+        assert_eq!(size("18446744073709551615KB"), None);
+        assert_eq!(size("17179869184GiB"), None);
+        assert_eq!(size("17179869183GiB"), Some(17179869183 << 30));
+    }
+}
@@ -0,0 +1,591 @@
+//! Detection of the language of pastes uploaded without an extension.
+
+use itertools::Itertools;
+use std::cmp::Reverse;
+
+use crate::Language;
+
+/// Lines at the start and the end of a file which may hold a modeline, like vim's default.
+const MODELINE_LINES: usize = 5;
+
+/// Lines which have to look like a language before it is considered.
+const MIN_SCORE: usize = 2;
+
+/// Languages with the best scores which are parsed to decide between them.
+const MAX_CANDIDATES: usize = 3;
+
+/// Share of syntax errors above which a text is not considered to be in a language.
+const MAX_ERROR_RATIO: f64 = 0.1;
+
+/// Bytes of a text which are parsed for each candidate, detection runs in request handlers.
+const MAX_PARSED_LEN: usize = 4 * 1024;
+
+/// Starts of lines typical for a language, each line starting with any of them counts.
+///
+/// Patterns ending in a word only match whole words, `fi` does not match `find`.
+const PATTERNS: &[(Language, &[&str])] = &[
+    (
+        Language::Bash,
+        &[
+            "echo ", "export ", "if [", "then", "fi", "do", "done", "esac", "set -", "local ",
+        ],
+    ),
+    (
+        Language::C,
+        &[
+            "#include ",
+            "#define ",
+            "#ifdef ",
+            "#ifndef ",
+            "#endif",
+            "typedef ",
+            "static ",
+            "int main(",
+            "printf(",
+            "return 0;",
+        ],
+    ),
+    (
+        Language::Cpp,
+        &[
+            "#include <",
+            "std::",
+            "using namespace ",
+            "namespace ",
+            "template",
+            "class ",
+            "public:",
+            "private:",
+        ],
+    ),
+    (
+        Language::Css,
+        &[
+            "@media",
+            "@import",
+            "@font-face",
+            ":root {",
+            "html {",
+            "body {",
+            "color: ",
+            "margin: ",
+            "padding: ",
+            "display: ",
+        ],
+    ),
+    (
+        Language::D,
+        &["module ", "import std.", "void main(", "writeln("],
+    ),
+    (
+        Language::Go,
+        &[
+            "package ",
+            "func ",
+            "import (",
+            "type ",
+            "fmt.",
+            "if err != nil",
+        ],
+    ),
+    (
+        Language::Haskell,
+        &[
+            "module ",
+            "import ",
+            "data ",
+            "newtype ",
+            "instance ",
+            "where",
+            "deriving ",
+            "main :: ",
+        ],
+    ),
+    (
+        Language::Java,
+        &[
+            "package ",
+            "import java",
+            "public class ",
+            "private ",
+            "@Override",
+            "public static void main",
+            "System.out.",
+        ],
+    ),
+    (
+        Language::JavaScript,
+        &[
+            "function ",
+            "async function ",
+            "const ",
+            "let ",
+            "var ",
+            "export ",
+            "import ",
+            "console.",
+            "module.exports",
+            "require(",
+        ],
+    ),
+    (
+        Language::Lua,
+        &["local ", "function ", "end", "elseif ", "require"],
+    ),
+    (
+        Language::Python,
+        &[
+            "def ",
+            "async def ",
+            "import ",
+            "from ",
+            "class ",
+            "elif ",
+            "print(",
+            "self.",
+            "if __name__",
+        ],
+    ),
+    (
+        Language::Rust,
+        &[
+            "fn ",
+            "pub fn ",
+            "pub(crate) ",
+            "use std::",
+            "use crate::",
+            "impl ",
+            "impl<",
+            "let mut ",
+            "#[derive(",
+            "mod ",
+            "pub struct ",
+            "pub enum ",
+            "struct ",
+            "enum ",
+        ],
+    ),
+    (
+        Language::Toml,
+        &[
+            "[package]",
+            "[dependencies]",
+            "[workspace]",
+            "[tool.",
+            "[[",
+            "name = ",
+            "version = ",
+        ],
+    ),
+    (
+        Language::Typescript,
+        &[
+            "interface ",
+            "export interface ",
+            "type ",
+            "export type ",
+            "import type ",
+            "enum ",
+            "readonly ",
+        ],
+    ),
+    (
+        Language::Yaml,
+        &[
+            "apiVersion:",
+            "kind:",
+            "name:",
+            "version:",
+            "services:",
+            "jobs:",
+            "steps:",
+            "runs-on:",
+        ],
+    ),
+];
+
+/// Language of a file with a well-known name, e.g. `Makefile`.
+pub(crate) fn from_file_name(path: &str) -> Option<Language> {
+    let name = path.rsplit(['/', '\\']).next()?;
+
+    match name {
+        ".bashrc" | ".bash_profile" | ".bash_logout" | ".profile" | ".zshrc" | ".zprofile"
+        | ".zshenv" | "PKGBUILD" | "APKBUILD" => Some(Language::Bash),
+        // they have no grammar of their own, but are mostly made of shell commands
+        "Dockerfile" | "Containerfile" | "Makefile" | "GNUmakefile" | "makefile" => {
+            Some(Language::Bash)
+        }
+        "SConstruct" | "SConscript" | "BUILD" | "WORKSPACE" | "Tiltfile" => Some(Language::Python),
+        "Cargo.lock" | "Pipfile" | "poetry.lock" => Some(Language::Toml),
+        ".clang-format" | ".clang-tidy" => Some(Language::Yaml),
+        _ => None,
+    }
+}
+
+/// Detects the language of text from its `head`, by its shebang, a modeline, its structure
+/// or finally by the lines typical for a language which are parsed with the fewest errors.
+pub(crate) fn from_content(head: &[u8]) -> Option<Language> {
+    // the start of a longer text may end in the middle of a character
+    let source = match std::str::from_utf8(head) {
+        Ok(source) => source,
+        Err(err) => std::str::from_utf8(&head[..err.valid_up_to()]).ok()?,
+    };
+    let lines = source.lines().collect::<Vec<_>>();
+
+    if let Some(language) = lines.first().and_then(|line| shebang(line)) {
+        return Some(language);
+    }
+
+    let edges = lines
+        .len()
+        .saturating_sub(MODELINE_LINES)
+        .max(MODELINE_LINES);
+    let modeline = lines
+        .iter()
+        .take(MODELINE_LINES)
+        .chain(lines.iter().skip(edges))
+        .find_map(|line| modeline(line));
+    if let Some(language) = modeline {
+        return Some(language);
+    }
+
+    structure(source, &lines).or_else(|| guess(source, &lines))
+}
+
+/// The language of an interpreter, e.g. `#!/usr/bin/env python3`.
+fn shebang(line: &str) -> Option<Language> {
+    let mut args = line.strip_prefix("#!")?.split_whitespace();
+    let mut interpreter = args.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        // options of env, e.g. `-S`
+        interpreter = args.find(|arg| !arg.starts_with('-'))?;
+    }
+
+    // versioned interpreters, e.g. `python3.11`
+    match interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+        "sh" | "bash" | "zsh" | "dash" | "ksh" | "ash" => Some(Language::Bash),
+        "python" | "pypy" => Some(Language::Python),
+        "node" | "nodejs" | "deno" | "bun" => Some(Language::JavaScript),
+        "ts-node" => Some(Language::Typescript),
+        "lua" | "luajit" => Some(Language::Lua),
+        "runhaskell" | "runghc" => Some(Language::Haskell),
+        "rdmd" => Some(Language::D),
+        "rust-script" => Some(Language::Rust),
+        _ => None,
+    }
+}
+
+/// The language of a vim modeline, e.g. `# vim: set ft=python:`,
+/// or of an Emacs one, e.g. `-*- mode: python -*-`.
+fn modeline(line: &str) -> Option<Language> {
+    if let Some((_, emacs)) = line.split_once("-*-") {
+        let variables = emacs.split("-*-").next()?;
+        // a single mode may be given without its name
+        let mode = match variables.contains(':') {
+            true => variables
+                .split(';')
+                .find_map(|variable| variable.trim().strip_prefix("mode:"))?,
+            false => variables,
+        };
+        return language_named(mode.trim());
+    }
+
+    let options = ["vim:", "vi:", "ex:"].iter().find_map(|tag| {
+        line.match_indices(tag)
+            .find(|(index, _)| {
+                line[..*index]
+                    .chars()
+                    .last()
+                    .is_none_or(char::is_whitespace)
+            })
+            .map(|(index, _)| &line[index + tag.len()..])
+    })?;
+
+    options
+        .split([' ', '\t', ':'])
+        .find_map(|option| {
+            option
+                .strip_prefix("ft=")
+                .or_else(|| option.strip_prefix("filetype="))
+                .or_else(|| option.strip_prefix("syntax="))
+        })
+        .and_then(language_named)
+}
+
+/// A language by the name editors know it by.
+fn language_named(name: &str) -> Option<Language> {
+    let name = name.to_lowercase();
+
+    Language::all()
+        .iter()
+        .copied()
+        .find(|language| language.as_str() == name)
+        .or_else(|| Language::from_extension(&name))
+        .or(match name.as_str() {
+            "c++" => Some(Language::Cpp),
+            "shell" | "shell-script" => Some(Language::Bash),
+            _ => None,
+        })
+}
+
+/// Formats which are recognized by their structure alone.
+fn structure(source: &str, lines: &[&str]) -> Option<Language> {
+    let start = source.trim_start();
+
+    let doctype = start.get(..14).unwrap_or_default();
+    if doctype.eq_ignore_ascii_case("<!doctype html") || start.starts_with("<html") {
+        return Some(Language::Html);
+    }
+
+    // only texts which fit into the head are complete
+    let is_json = start.starts_with(['{', '['])
+        && serde_json::from_str::<serde::de::IgnoredAny>(source).is_ok();
+    if is_json {
+        return Some(Language::Json);
+    }
+
+    let is_diff = lines.iter().any(|line| line.starts_with("diff --git "))
+        || lines.iter().tuple_windows().any(|(old, new, hunk)| {
+            old.starts_with("--- ") && new.starts_with("+++ ") && hunk.starts_with("@@ ")
+        });
+    if is_diff {
+        return Some(Language::Diff);
+    }
+
+    None
+}
+
+/// Guesses the language from the lines typical for it, the grammars of the most likely
+/// languages decide between them.
+fn guess(source: &str, lines: &[&str]) -> Option<Language> {
+    let mut candidates = PATTERNS
+        .iter()
+        .map(|(language, patterns)| {
+            let score = lines
+                .iter()
+                .map(|line| line.trim_start())
+                .filter(|line| {
+                    patterns
+                        .iter()
+                        .any(|pattern| starts_with_word(line, pattern))
+                })
+                .count();
+            (*language, score)
+        })
+        .filter(|(_, score)| *score >= MIN_SCORE)
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|(_, score)| Reverse(*score));
+    candidates.truncate(MAX_CANDIDATES);
+
+    // ties are won by the better score, prose is not parsed well by any of them
+    let source = parsed_prefix(source);
+    candidates
+        .into_iter()
+        .filter_map(|(language, _)| Some((language, error_ratio(language, source)?)))
+        .filter(|(_, ratio)| *ratio <= MAX_ERROR_RATIO)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(language, _)| language)
+}
+
+fn starts_with_word(line: &str, pattern: &str) -> bool {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+
+    match line.strip_prefix(pattern) {
+        Some(rest) if pattern.ends_with(is_word) => !rest.starts_with(is_word),
+        Some(_) => true,
+        None => false,
+    }
+}
+
+/// The whole lines within the first [`MAX_PARSED_LEN`] bytes of `source`, a line cut in
+/// half would be a syntax error.
+fn parsed_prefix(source: &str) -> &str {
+    if source.len() <= MAX_PARSED_LEN {
+        return source;
+    }
+
+    let mut end = MAX_PARSED_LEN;
+    while !source.is_char_boundary(end) {
+        end -= 1;
+    }
+    let prefix = &source[..end];
+    match prefix.rfind('\n') {
+        Some(index) => &prefix[..=index],
+        None => prefix,
+    }
+}
+
+/// Share of the nodes of the syntax tree of `source` which are errors.
+fn error_ratio(language: Language, source: &str) -> Option<f64> {
+    let mut parser = tree_sitter::Parser::new();
+    parser.set_language(language.grammar()?).ok()?;
+    let tree = parser.parse(source, None)?;
+
+    let (mut nodes, mut errors) = (0u32, 0u32);
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        nodes += 1;
+        if node.is_error() || node.is_missing() {
+            errors += 1;
+        }
+
+        if cursor.goto_first_child() || cursor.goto_next_sibling() {
+            continue;
+        }
+        // back up to the next node which was not visited yet, the root has no siblings
+        loop {
+            if !cursor.goto_parent() {
+                return Some(f64::from(errors) / f64::from(nodes));
+            }
+            if cursor.goto_next_sibling() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(source: &str) -> Option<&'static str> {
+        from_content(source.as_bytes()).map(|language| language.as_str())
+    }
+
+    #[test]
+    fn parsed_prefixes() {
+        assert_eq!(parsed_prefix("fn main() {}\n"), "fn main() {}\n");
+
+        let line = format!("{}\n", "x".repeat(99));
+        let source = line.repeat(100);
+        let prefix = parsed_prefix(&source);
+        assert_eq!(prefix.len(), MAX_PARSED_LEN / 100 * 100);
+        assert!(prefix.ends_with('\n'));
+
+        // a single long line is cut at a character boundary
+        let source = "ä".repeat(MAX_PARSED_LEN);
+        let prefix = parsed_prefix(&source);
+        assert_eq!(prefix.len(), MAX_PARSED_LEN);
+        let source = format!("x{source}");
+        assert_eq!(parsed_prefix(&source).len(), MAX_PARSED_LEN - 1);
+    }
+
+    #[test]
+    fn long_texts() {
+        let mut source = "use std::io;\n\nfn main() {\n    let x = 1;\n}\n".repeat(200);
+        // only a prefix is parsed, errors after it do not count
+        source.push_str(&"%%%% ?? \n".repeat(1000));
+        assert!(source.len() > MAX_PARSED_LEN);
+        assert_eq!(detect(&source), Some("rust"));
+    }
+
+    #[test]
+    fn file_names() {
+        let name = |path| from_file_name(path).map(|language| language.as_str());
+
+        assert_eq!(name("Makefile"), Some("bash"));
+        assert_eq!(name("project/Cargo.lock"), Some("toml"));
+        assert_eq!(name("C:\\project\\SConstruct"), Some("python"));
+        assert_eq!(name("main.rs"), None);
+    }
+
+    #[test]
+    fn shebangs() {
+        assert_eq!(detect("#!/bin/sh\nls\n"), Some("bash"));
+        assert_eq!(detect("#!/usr/bin/python3.11\n"), Some("python"));
+        assert_eq!(detect("#!/usr/bin/env python3\n"), Some("python"));
+        assert_eq!(
+            detect("#!/usr/bin/env -S deno run --allow-net\n"),
+            Some("javascript")
+        );
+        assert_eq!(detect("#!/usr/bin/env -S -i ts-node\n"), Some("typescript"));
+
+        assert!(shebang("#!/usr/bin/perl -w").is_none());
+        assert!(shebang("#!/usr/bin/env -S").is_none());
+        assert!(shebang("# not a shebang").is_none());
+    }
+
+    #[test]
+    fn vim_modelines() {
+        let lang = |line| modeline(line).map(|language| language.as_str());
+
+        assert_eq!(lang("# vim: set ft=python:"), Some("python"));
+        assert_eq!(lang("// vim: ts=4 sw=4 filetype=rust"), Some("rust"));
+        assert_eq!(lang("/* vi:syntax=c++ */"), Some("cpp"));
+        assert_eq!(lang("<!-- ex: set ft=html: -->"), Some("html"));
+        // tags have to start a word
+        assert_eq!(lang("# evim: ft=python"), None);
+        assert_eq!(lang("# vim: set ts=4:"), None);
+    }
+
+    #[test]
+    fn emacs_modelines() {
+        let lang = |line| modeline(line).map(|language| language.as_str());
+
+        assert_eq!(
+            lang("# -*- mode: python; coding: utf-8 -*-"),
+            Some("python")
+        );
+        assert_eq!(lang("// -*- C++ -*-"), Some("cpp"));
+        assert_eq!(lang("# -*- shell-script -*-"), Some("bash"));
+        assert_eq!(lang("# -*- coding: utf-8 -*-"), None);
+    }
+
+    #[test]
+    fn modeline_positions() {
+        let text = "some words\n".repeat(20);
+
+        assert_eq!(detect(&format!("{text}# vim: ft=yaml\n")), Some("yaml"));
+        assert_eq!(detect(&format!("# vim: ft=yaml\n{text}")), Some("yaml"));
+        // only the first and last lines are searched, like vim does
+        assert_eq!(detect(&format!("{text}# vim: ft=yaml\n{text}")), None);
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(detect("{\"a\": [1, 2.5, null]}"), Some("json"));
+        assert_eq!(detect("  [\n  {\"a\": true}\n]\n"), Some("json"));
+        // only complete documents
+        assert_ne!(detect("{\"a\": [1, 2"), Some("json"));
+    }
+
+    #[test]
+    fn diffs() {
+        let git = "diff --git a/a.rs b/a.rs\nindex 1..2 100644\n--- a/a.rs\n+++ b/a.rs\n@@ -1 +1 @@\n-a\n+b\n";
+        assert_eq!(detect(git), Some("diff"));
+
+        let unified = "Some description\n\n--- a.txt\n+++ a.txt\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n";
+        assert_eq!(detect(unified), Some("diff"));
+
+        // headers without a hunk
+        assert_ne!(detect("--- a.txt\n+++ a.txt\n"), Some("diff"));
+    }
+
+    #[test]
+    fn html() {
+        assert_eq!(detect("<!DOCTYPE html>\n<html></html>"), Some("html"));
+        assert_eq!(detect("\n<html lang=\"en\">"), Some("html"));
+    }
+
+    #[test]
+    fn guessed() {
+        let rust = "use std::io;\n\nfn main() {\n    let x = 1;\n    println!(\"{x}\");\n}\n\nfn other() {}\n";
+        assert_eq!(detect(rust), Some("rust"));
+
+        let python = "import os\nimport sys\n\ndef main():\n    print(os.getcwd())\n\nif __name__ == '__main__':\n    main()\n";
+        assert_eq!(detect(python), Some("python"));
+    }
+
+    #[test]
+    fn prose() {
+        let prose = "Hello there,\n\n\
+                     if you read this, then do let me know. I am done with the\n\
+                     export of the photos, for the echo of the last trip is fading.\n\
+                     Use the link below and import them into your library.\n\n\
+                     Best regards\n";
+        assert_eq!(detect(prose), None);
+        assert_eq!(detect(""), None);
+        assert_eq!(detect("just a single line"), None);
+    }
+}
@@ -6,9 +6,15 @@ pub enum Error {
     #[error("not found")]
     NotFound,
 
+    #[error("paste expired")]
+    Gone,
+
     #[error("bad request")]
     BadRequest,
 
+    #[error("invalid deletion token")]
+    InvalidToken,
+
     #[error("storage error")]
     StorageError,
 
@@ -23,18 +29,50 @@ pub enum Error {
 
     #[error("missing file")]
     MissingFile,
+
+    #[error("paste too large, the limit is {0} bytes")]
+    PayloadTooLarge(u64),
+
+    #[error("only single text pastes can be compared")]
+    NotComparable,
+
+    #[error("pastes of several files cannot burn after reading")]
+    BurnSeveralFiles,
 }
 
 impl Error {
-    fn status_code(&self) -> StatusCode {
+    pub(crate) fn status_code(&self) -> StatusCode {
         match self {
             Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Gone => StatusCode::GONE,
             Self::BadRequest => StatusCode::BAD_REQUEST,
+            Self::InvalidToken => StatusCode::FORBIDDEN,
             Self::StorageError => StatusCode::INTERNAL_SERVER_ERROR,
             Self::NotUtf8 => StatusCode::BAD_REQUEST,
             Self::Empty => StatusCode::BAD_REQUEST,
             Self::UnsupportedFile(..) => StatusCode::BAD_REQUEST,
             Self::MissingFile => StatusCode::BAD_REQUEST,
+            Self::PayloadTooLarge(..) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::NotComparable => StatusCode::BAD_REQUEST,
+            Self::BurnSeveralFiles => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// Stable identifier of the error, e.g. for API clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound => "not_found",
+            Self::Gone => "gone",
+            Self::BadRequest => "bad_request",
+            Self::InvalidToken => "invalid_token",
+            Self::StorageError => "storage_error",
+            Self::NotUtf8 => "not_utf8",
+            Self::Empty => "empty",
+            Self::UnsupportedFile(..) => "unsupported_file",
+            Self::MissingFile => "missing_file",
+            Self::PayloadTooLarge(..) => "payload_too_large",
+            Self::NotComparable => "not_comparable",
+            Self::BurnSeveralFiles => "burn_several_files",
         }
     }
 }
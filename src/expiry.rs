@@ -0,0 +1,149 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+/// How long a paste is kept around.
+///
+/// Parsed from strings like `30s`, `10m`, `1h`, `1d`, `2w` or `never`.
+/// `After` is ordered before `Never`, so `min` can be used to cap an expiry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Expiry {
+    After(Duration),
+    #[default]
+    Never,
+}
+
+impl Expiry {
+    /// Unix timestamp (seconds) at which a paste created at `now` expires.
+    pub fn expires_at(&self, now: u64) -> Option<u64> {
+        match self {
+            Self::After(duration) => Some(now.saturating_add(duration.as_secs())),
+            Self::Never => None,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("invalid expiry '{0}', expected e.g. '10m', '1d' or 'never'")]
+pub struct ParseExpiryError(String);
+
+impl FromStr for Expiry {
+    type Err = ParseExpiryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("never") {
+            return Ok(Self::Never);
+        }
+
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (value, unit) = s.split_at(split);
+
+        let value: u64 = value.parse().map_err(|_| ParseExpiryError(s.to_owned()))?;
+        let multiplier = match unit {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => return Err(ParseExpiryError(s.to_owned())),
+        };
+
+        value
+            .checked_mul(multiplier)
+            .filter(|&secs| secs > 0)
+            .map(|secs| Self::After(Duration::from_secs(secs)))
+            .ok_or_else(|| ParseExpiryError(s.to_owned()))
+    }
+}
+
+impl fmt::Display for Expiry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::After(duration) => write!(f, "{}s", duration.as_secs()),
+            Self::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl<'de> serde::de::Deserialize<'de> for Expiry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::{Error, Unexpected};
+
+        let s = String::deserialize(deserializer)?;
+
+        s.parse()
+            .map_err(|_| Error::invalid_value(Unexpected::Str(&s), &"a valid expiry"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn after(secs: u64) -> Expiry {
+        Expiry::After(Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("30".parse::<Expiry>().unwrap(), after(30));
+        assert_eq!("30s".parse::<Expiry>().unwrap(), after(30));
+        assert_eq!("10m".parse::<Expiry>().unwrap(), after(600));
+        assert_eq!("1h".parse::<Expiry>().unwrap(), after(3600));
+        assert_eq!("1d".parse::<Expiry>().unwrap(), after(86400));
+        assert_eq!("2w".parse::<Expiry>().unwrap(), after(1209600));
+        assert_eq!("never".parse::<Expiry>().unwrap(), Expiry::Never);
+        assert_eq!(" Never ".parse::<Expiry>().unwrap(), Expiry::Never);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for s in [
+            "",
+            "d",
+            "0",
+            "0d",
+            "1y",
+            "1.5h",
+            "-1d",
+            "1 d",
+            "99999999999999999999w",
+        ] {
+            assert!(s.parse::<Expiry>().is_err(), "{s:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for expiry in [after(90), Expiry::Never] {
+            assert_eq!(expiry.to_string().parse::<Expiry>().unwrap(), expiry);
+        }
+    }
+
+    #[test]
+    fn clamped_to_max_expiry() {
+        let config = Config {
+            default_expiry: after(3600),
+            max_expiry: after(86400),
+            ..Config::default()
+        };
+        assert_eq!(config.expiry(None), after(3600));
+        assert_eq!(config.expiry(Some(after(60))), after(60));
+        assert_eq!(config.expiry(Some(after(604800))), after(86400));
+        assert_eq!(config.expiry(Some(Expiry::Never)), after(86400));
+
+        let config = Config::default();
+        assert_eq!(config.expiry(None), Expiry::Never);
+        assert_eq!(config.expiry(Some(after(60))), after(60));
+    }
+
+    #[test]
+    fn expires_at() {
+        assert_eq!(after(60).expires_at(1000), Some(1060));
+        assert_eq!(after(60).expires_at(u64::MAX), Some(u64::MAX));
+        assert_eq!(Expiry::Never.expires_at(1000), None);
+    }
+}
@@ -1,147 +1,2028 @@
 use crate::{
-    highlight::Highlighted,
-    storage, templates,
-    utils::{File, Protocol},
-    Error, Language, PasteId, Result, StorageExtension, Theme, ThemeExtension, WithExtension,
-    MAX_FILE_SIZE,
+    archive, detect,
+    id::generate_token,
+    storage,
+    storage::{Paste, PasteData, PasteFile},
+    templates,
+    utils::{unix_now, ClientIp, FileKind, Protocol, UploadBody, UploadReader},
+    Config, ConfigExtension, Encoding, Error, Expiry, Language, PasteId, PasteMetadata, Result,
+    Storage, StorageExtension, Theme, ThemeExtension, WithExtension,
 };
 use axum::{
-    extract::{ContentLengthLimit, Host, Multipart, Path},
+    body::{Bytes, StreamBody},
+    extract::{BodyStream, Host, Path, Query},
     response::{Html, IntoResponse, Response},
     Extension,
 };
-use hyper::{header, HeaderMap};
-use tokio::io::AsyncReadExt;
+use futures_util::TryStreamExt;
+use hyper::{header, header::HeaderValue, HeaderMap, Method, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    io,
+    net::IpAddr,
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::io::{ReaderStream, StreamReader};
 
 pub async fn root() -> impl IntoResponse {
-    Html(templates::Index.to_string())
+    Html(templates::Index::default().to_string())
 }
 
 pub async fn view(
     Path(WithExtension(id, ext)): Path<WithExtension<PasteId>>,
+    method: Method,
+    headers: HeaderMap,
     Extension(storage): StorageExtension,
     Extension(theme): ThemeExtension,
 ) -> Result<impl IntoResponse> {
-    let mut data = Vec::new();
+    if method == Method::HEAD {
+        let metadata = storage.metadata(&id).await.map_err(load_error)?;
+        let representation = shown_as(&metadata, accepts_html(&headers), ext.as_deref());
+        return Ok(head(&headers, metadata, representation));
+    }
+    show(&headers, &*storage, &theme, &id, ext).await
+}
 
-    storage
-        .load(&id)
-        .await
-        .map_err(|e| match e {
-            storage::LoadError::NotFound => Error::NotFound,
-            _ => Error::StorageError,
-        })?
-        .read_to_end(&mut data)
+/// Shows revision `rev` of a paste like [`view`], revision 1 is the original paste itself.
+pub async fn revision(
+    Path((id, WithExtension(rev, ext))): Path<(PasteId, WithExtension<String>)>,
+    method: Method,
+    headers: HeaderMap,
+    Extension(storage): StorageExtension,
+    Extension(theme): ThemeExtension,
+) -> Result<impl IntoResponse> {
+    let (original, _) = original(&*storage, &id).await?;
+
+    let id = match rev.parse::<usize>().map_err(|_| Error::NotFound)? {
+        0 => return Err(Error::NotFound),
+        1 => original,
+        rev => storage
+            .revisions(&original)
+            .await
+            .map_err(load_error)?
+            .into_iter()
+            .nth(rev - 2)
+            .ok_or(Error::NotFound)?,
+    };
+
+    if method == Method::HEAD {
+        let metadata = storage.metadata(&id).await.map_err(load_error)?;
+        let representation = shown_as(&metadata, accepts_html(&headers), ext.as_deref());
+        return Ok(head(&headers, metadata, representation));
+    }
+    show(&headers, &*storage, &theme, &id, ext).await
+}
+
+/// The original paste of `id` and its metadata, `id` itself unless it is a revision.
+async fn original(
+    storage: &(dyn Storage + Send + Sync),
+    id: &PasteId,
+) -> Result<(PasteId, PasteMetadata)> {
+    let metadata = storage.metadata(id).await.map_err(load_error)?;
+
+    match metadata.revision_of.clone().map(PasteId::new) {
+        Some(Ok(original)) => {
+            let metadata = storage.metadata(&original).await.map_err(load_error)?;
+            Ok((original, metadata))
+        }
+        _ => Ok((id.clone(), metadata)),
+    }
+}
+
+/// Lists all revisions of a paste with their creation time, never burns them.
+pub async fn history(
+    Path(WithExtension(id, _)): Path<WithExtension<PasteId>>,
+    Protocol(protocol): Protocol,
+    Host(host): Host,
+    headers: HeaderMap,
+    Extension(storage): StorageExtension,
+) -> Result<impl IntoResponse> {
+    let (original, metadata) = original(&*storage, &id).await?;
+
+    let mut revisions = vec![(1, original.clone(), metadata)];
+    let ids = storage.revisions(&original).await.map_err(load_error)?;
+    for (index, id) in ids.into_iter().enumerate() {
+        // revisions are deleted or expire on their own
+        if let Ok(metadata) = storage.metadata(&id).await {
+            revisions.push((index + 2, id, metadata));
+        }
+    }
+
+    let mut previous: Option<PasteId> = None;
+    let revisions = revisions
+        .into_iter()
+        .map(|(number, id, metadata)| templates::Revision {
+            number,
+            url: format!("{protocol}://{host}/{original}/rev/{number}"),
+            diff: previous
+                .replace(id.clone())
+                .map(|previous| format!("{protocol}://{host}/diff/{previous}/{id}")),
+            created_at: last_modified(&metadata)
+                .map(httpdate::fmt_http_date)
+                .unwrap_or_default(),
+            size: metadata.size,
+        })
+        .collect::<Vec<_>>();
+
+    // new revisions are added to the history at any time
+    let cache_control = [(header::CACHE_CONTROL, "no-cache")];
+
+    if accepts_html(&headers) {
+        let page = templates::History {
+            id: &original,
+            revisions: &revisions,
+        };
+        return Ok((cache_control, Html(page.to_string())).into_response());
+    }
+
+    let list = revisions
+        .iter()
+        .map(|rev| format!("{}\t{}\t{}\n", rev.number, rev.created_at, rev.url))
+        .collect::<String>();
+    Ok((cache_control, list).into_response())
+}
+
+/// Responds with a paste, highlighted for browsers or as is, see [`view`].
+async fn show(
+    headers: &HeaderMap,
+    storage: &(dyn Storage + Send + Sync),
+    theme: &Theme,
+    id: &PasteId,
+    ext: Option<String>,
+) -> Result<Response> {
+    let is_html = accepts_html(headers);
+
+    // Raw views can be served compressed, if the paste is stored with an accepted encoding.
+    let accepted = match ext.is_none() && !is_html {
+        true => accepted_encodings(headers),
+        false => Vec::new(),
+    };
+    let mut paste = load(storage, id, &accepted).await?;
+
+    // Pastes of several files are shown on a single page, or downloaded as a tarball.
+    if !paste.metadata.files.is_empty() {
+        return match is_html && ext.as_deref() != Some("tar") {
+            true => bundle_page(headers, theme, id, paste).await,
+            false => bundle_archive(headers, id, paste).await,
+        };
+    }
+
+    // Only the browser has the key, it gets a page which decrypts the paste.
+    if paste.metadata.client_encrypted && is_html {
+        let headers_out = cache_headers(&paste.metadata, Some("html"));
+        if let Some(response) = not_modified(headers, headers_out.clone()) {
+            return Ok(response);
+        }
+
+        let page = templates::Decrypt {
+            data: &base64::encode(read(&mut paste).await?),
+        };
+        return Ok((headers_out, Html(page.to_string())).into_response());
+    }
+
+    if paste.metadata.client_encrypted || paste.metadata.encoding.is_some() {
+        return raw_response(headers, paste).await;
+    }
+
+    // Browsers get the paste highlighted with the extension it was uploaded with,
+    // everyone else keeps getting the raw paste.
+    let ext = match ext {
+        Some(ext) => Some(ext),
+        None if is_html => paste.metadata.extension.take(),
+        None => None,
+    };
+
+    let is_text = matches!(&paste.metadata.content_type, Some(ct) if ct.starts_with("text/"));
+    match ext {
+        Some(ext) if is_text => {
+            let headers_out = cache_headers(&paste.metadata, Some("html"));
+            if let Some(response) = not_modified(headers, headers_out.clone()) {
+                return Ok(response);
+            }
+
+            let source =
+                String::from_utf8(read(&mut paste).await?).map_err(|_| Error::StorageError)?;
+            Ok((headers_out, view_paste(theme, source, &ext)).into_response())
+        }
+        _ => raw_response(headers, paste).await,
+    }
+}
+
+/// Serves the stored paste as is, e.g. for CLI clients of client side encrypted pastes.
+pub async fn raw(
+    Path(WithExtension(id, _)): Path<WithExtension<PasteId>>,
+    method: Method,
+    headers: HeaderMap,
+    Extension(storage): StorageExtension,
+) -> Result<impl IntoResponse> {
+    if method == Method::HEAD {
+        let metadata = storage.metadata(&id).await.map_err(load_error)?;
+        let representation = match metadata.files.is_empty() {
+            true => None,
+            false => Some("tar"),
+        };
+        return Ok(head(&headers, metadata, representation));
+    }
+
+    let paste = load(&*storage, &id, &accepted_encodings(&headers)).await?;
+
+    if !paste.metadata.files.is_empty() {
+        return bundle_archive(&headers, &id, paste).await;
+    }
+    raw_response(&headers, paste).await
+}
+
+/// Serves a single file of a paste holding several files, highlighted for browsers.
+pub async fn file(
+    Path((id, name)): Path<(PasteId, String)>,
+    method: Method,
+    headers: HeaderMap,
+    Extension(storage): StorageExtension,
+    Extension(theme): ThemeExtension,
+) -> Result<impl IntoResponse> {
+    if method == Method::HEAD {
+        let metadata = storage.metadata(&id).await.map_err(load_error)?;
+        let (_, metadata) = file_metadata(metadata, &name)?;
+        let representation = match metadata.content_type.as_deref() {
+            Some(ct) if ct.starts_with("text/") && accepts_html(&headers) => Some("html"),
+            _ => None,
+        };
+        return Ok(head(&headers, metadata, representation));
+    }
+
+    let Paste { mut data, metadata } = load(&*storage, &id, &[]).await?;
+    let (offset, metadata) = file_metadata(metadata, &name)?;
+
+    tokio::io::copy(&mut (&mut data).take(offset), &mut tokio::io::sink())
         .await
         .map_err(|_| Error::StorageError)?;
 
-    let response = match File::infer(&data).map_err(|_| Error::StorageError)? {
-        File::Binary(_, ft) => view_bin(ft, data)?.into_response(),
-        File::Text(_, _) => {
-            // safe because File::Text validates for UTF-8
-            view_paste(&theme, unsafe { String::from_utf8_unchecked(data) }, ext)?.into_response()
+    let is_text = matches!(&metadata.content_type, Some(ct) if ct.starts_with("text/"));
+    let mut paste = Paste {
+        data: Box::new(data.take(metadata.size)),
+        metadata,
+    };
+
+    if !(is_text && accepts_html(&headers)) {
+        return raw_response(&headers, paste).await;
+    }
+
+    let headers_out = cache_headers(&paste.metadata, Some("html"));
+    if let Some(response) = not_modified(&headers, headers_out.clone()) {
+        return Ok(response);
+    }
+
+    let ext = paste.metadata.extension.take().unwrap_or_default();
+    let source = String::from_utf8(read(&mut paste).await?).map_err(|_| Error::StorageError)?;
+    Ok((headers_out, view_paste(&theme, source, &ext)).into_response())
+}
+
+/// The metadata of the file `name` of a paste holding several files, and its offset in
+/// the contents of the paste.
+fn file_metadata(metadata: PasteMetadata, name: &str) -> Result<(u64, PasteMetadata)> {
+    let index = metadata
+        .files
+        .iter()
+        .position(|file| file.name == name)
+        .ok_or(Error::NotFound)?;
+    let offset = metadata.files[..index].iter().map(|file| file.size).sum();
+    let file = metadata.files[index].clone();
+
+    let metadata = PasteMetadata {
+        file_name: Some(file.name),
+        extension: file.extension,
+        content_type: Some(file.content_type),
+        size: file.size,
+        content_digest: Some(file.digest),
+        files: Vec::new(),
+        ..metadata
+    };
+    Ok((offset, metadata))
+}
+
+/// The representation [`show`] responds with for a paste, see [`cache_headers`].
+fn shown_as(metadata: &PasteMetadata, is_html: bool, ext: Option<&str>) -> Option<&'static str> {
+    let is_text = matches!(&metadata.content_type, Some(ct) if ct.starts_with("text/"));
+
+    if !metadata.files.is_empty() {
+        return match is_html && ext != Some("tar") {
+            true => Some("html"),
+            false => Some("tar"),
+        };
+    }
+    let is_page = match metadata.client_encrypted {
+        true => is_html,
+        false => is_text && (ext.is_some() || (is_html && metadata.extension.is_some())),
+    };
+    is_page.then_some("html")
+}
+
+/// Answers a `HEAD` request from the metadata of a paste alone, loading the paste would
+/// burn it.
+fn head(
+    request: &HeaderMap,
+    mut metadata: PasteMetadata,
+    representation: Option<&str>,
+) -> Response {
+    // responses are decoded, like for clients which accept no encoding
+    metadata.encoding = None;
+
+    let mut headers = cache_headers(&metadata, representation);
+    if let Some(response) = not_modified(request, headers.clone()) {
+        return response;
+    }
+
+    let content_type = match representation {
+        Some("html") => Some("text/html; charset=utf-8"),
+        Some("tar") => Some("application/x-tar"),
+        _ => metadata.content_type.as_deref(),
+    };
+    if let Some(content_type) = content_type.and_then(|ct| ct.parse().ok()) {
+        headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    if representation.is_none() {
+        headers.insert(header::CONTENT_LENGTH, metadata.size.into());
+        if !metadata.burn_after_reading {
+            headers.insert(header::ACCEPT_RANGES, "bytes".parse().unwrap());
         }
+    }
+    headers.into_response()
+}
+
+/// Shows all files of a paste on one page, each one highlighted with its own extension.
+async fn bundle_page(
+    request: &HeaderMap,
+    theme: &Theme,
+    id: &PasteId,
+    mut paste: Paste,
+) -> Result<Response> {
+    let headers = cache_headers(&paste.metadata, Some("html"));
+    if let Some(response) = not_modified(request, headers.clone()) {
+        return Ok(response);
+    }
+
+    let data = read(&mut paste).await?;
+    let files = split_files(&paste.metadata.files, &data)?
+        .into_iter()
+        .map(|(file, contents)| {
+            let (source, is_escaped) = match file.content_type.starts_with("text/") {
+                true => {
+                    let source = std::str::from_utf8(contents).map_err(|_| Error::StorageError)?;
+                    highlight(theme, source, file.extension.as_deref().unwrap_or_default())
+                }
+                false => (Vec::new(), false),
+            };
+
+            Ok(templates::BundleFile {
+                name: &file.name,
+                source,
+                is_escaped,
+                is_image: file.content_type.starts_with("image/"),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let page = templates::Bundle {
+        css: theme.css(),
+        id,
+        files: &files,
     };
+    Ok((headers, Html(page.to_string())).into_response())
+}
+
+/// Downloads all files of a paste as a tarball.
+async fn bundle_archive(request: &HeaderMap, id: &PasteId, mut paste: Paste) -> Result<Response> {
+    let mut headers = cache_headers(&paste.metadata, Some("tar"));
+    if let Some(response) = not_modified(request, headers.clone()) {
+        return Ok(response);
+    }
+
+    let data = read(&mut paste).await?;
+    let files = split_files(&paste.metadata.files, &data)?
+        .into_iter()
+        .map(|(file, contents)| (format!("{id}/{}", file.name), contents));
+    let archive = archive::tar(files, paste.metadata.created_at).ok_or(Error::StorageError)?;
+
+    headers.insert(header::CONTENT_TYPE, "application/x-tar".parse().unwrap());
+    let disposition = format!("attachment; filename=\"{id}.tar\"");
+    headers.insert(header::CONTENT_DISPOSITION, disposition.parse().unwrap());
+    Ok((headers, archive).into_response())
+}
+
+/// Splits the contents of a paste holding several files into its files.
+fn split_files<'a>(
+    files: &'a [PasteFile],
+    mut data: &'a [u8],
+) -> Result<Vec<(&'a PasteFile, &'a [u8])>> {
+    files
+        .iter()
+        .map(|file| {
+            let size = usize::try_from(file.size)
+                .ok()
+                .filter(|&size| size <= data.len())
+                .ok_or(Error::StorageError)?;
+            let (contents, rest) = data.split_at(size);
+            data = rest;
+            Ok((file, contents))
+        })
+        .collect()
+}
+
+/// Loads a paste, its contents may stay in one of the `accepted` encodings.
+async fn load(
+    storage: &(dyn Storage + Send + Sync),
+    id: &PasteId,
+    accepted: &[Encoding],
+) -> Result<Paste> {
+    let mut paste = storage
+        .load_encoded(id, accepted)
+        .await
+        .map_err(load_error)?;
+
+    // pastes of several files are split into their files, which needs the decoded contents
+    if !paste.metadata.files.is_empty() {
+        paste = storage::decode(paste, &[]);
+    }
+
+    // pastes saved before the content type was stored are read to infer it
+    if paste.metadata.content_type.is_none() {
+        let data = read(&mut paste).await?;
+        let content_type = FileKind::infer(&data)
+            .map(|kind| kind.content_type())
+            .unwrap_or("application/octet-stream");
+
+        paste.metadata.content_type = Some(content_type.to_owned());
+        paste.metadata.size = data.len() as u64;
+        paste.data = Box::new(io::Cursor::new(data));
+    }
+
+    Ok(paste)
+}
+
+async fn read(paste: &mut Paste) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    paste
+        .data
+        .read_to_end(&mut data)
+        .await
+        .map_err(|_| Error::StorageError)?;
+    Ok(data)
+}
+
+/// How long pastes are cached by clients, one year.
+const MAX_AGE: u64 = 365 * 24 * 60 * 60;
 
+fn last_modified(metadata: &PasteMetadata) -> Option<SystemTime> {
+    // pastes saved before their metadata have no creation time
+    (metadata.created_at > 0).then(|| UNIX_EPOCH + Duration::from_secs(metadata.created_at))
+}
+
+fn http_date(value: Option<&HeaderValue>) -> Option<SystemTime> {
+    httpdate::parse_http_date(value?.to_str().ok()?).ok()
+}
+
+/// Caching headers of a `representation` of the paste, e.g. `html` or its encoding,
+/// `None` for the paste as is.
+///
+/// Burned pastes get no validators, a conditional request would burn them without
+/// serving them. Expiring pastes are cached at most until they expire.
+fn cache_headers(metadata: &PasteMetadata, representation: Option<&str>) -> HeaderMap {
     let mut headers = HeaderMap::new();
+    headers.insert(header::VARY, "Accept, Accept-Encoding".parse().unwrap());
+
+    if metadata.burn_after_reading {
+        headers.insert(header::CACHE_CONTROL, "no-store".parse().unwrap());
+        return headers;
+    }
+    let max_age = match metadata.expires_at {
+        Some(expires_at) => expires_at.saturating_sub(unix_now()).min(MAX_AGE),
+        None => MAX_AGE,
+    };
     headers.insert(
         header::CACHE_CONTROL,
-        "public, max-age=31536000".parse().unwrap(),
+        format!("public, max-age={max_age}").parse().unwrap(),
     );
 
-    Ok((headers, response))
+    if let Some(digest) = &metadata.content_digest {
+        let etag = match representation {
+            Some(representation) => format!("\"{digest}-{representation}\""),
+            None => format!("\"{digest}\""),
+        };
+        headers.insert(header::ETAG, etag.parse().unwrap());
+    }
+    if let Some(last_modified) = last_modified(metadata) {
+        let last_modified = httpdate::fmt_http_date(last_modified);
+        headers.insert(header::LAST_MODIFIED, last_modified.parse().unwrap());
+    }
+    headers
 }
 
-fn view_bin(ft: infer::Type, data: Vec<u8>) -> Result<impl IntoResponse> {
-    let content_type = match ft.matcher_type() {
-        // Surely this will never fail
-        infer::MatcherType::Image => ft.mime_type().parse().unwrap(),
-        // All other content types are not supported and an error,
-        // this should never happen because it is checked on upload.
-        _ => return Err(Error::StorageError),
+/// Answers with `304 Not Modified` if the client's copy is still current, according to
+/// the validators in the response `headers`.
+fn not_modified(request: &HeaderMap, headers: HeaderMap) -> Option<Response> {
+    let is_current = match request.get(header::IF_NONE_MATCH) {
+        Some(if_none_match) => {
+            let etag = headers.get(header::ETAG)?.to_str().ok()?;
+            if_none_match
+                .to_str()
+                .ok()?
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag)
+        }
+        // only used without `If-None-Match`
+        None => {
+            let last_modified = http_date(headers.get(header::LAST_MODIFIED))?;
+            last_modified <= http_date(request.get(header::IF_MODIFIED_SINCE))?
+        }
     };
 
-    let mut headers = HeaderMap::new();
-    headers.insert(header::CONTENT_TYPE, content_type);
+    is_current.then(|| (StatusCode::NOT_MODIFIED, headers).into_response())
+}
+
+/// Streams the paste, or the part of it requested with `Range`.
+async fn raw_response(request: &HeaderMap, paste: Paste) -> Result<Response> {
+    let Paste { mut data, metadata } = paste;
+
+    let mut headers = cache_headers(&metadata, metadata.encoding.map(|e| e.as_str()));
+    if let Some(response) = not_modified(request, headers.clone()) {
+        return Ok(response);
+    }
+
+    // set by `load`
+    let content_type = metadata.content_type.as_deref().unwrap_or_default();
+    headers.insert(
+        header::CONTENT_TYPE,
+        content_type.parse().map_err(|_| Error::StorageError)?,
+    );
+
+    // The size is only known for the decoded paste, encoded pastes are sent without it.
+    if let Some(encoding) = metadata.encoding {
+        headers.insert(header::CONTENT_ENCODING, encoding.as_str().parse().unwrap());
+        return Ok((headers, body(data)).into_response());
+    }
+
+    let size = metadata.size;
+    if size == 0 {
+        return Ok((headers, body(data)).into_response());
+    }
+
+    // A partial request would burn the paste before the rest of it could be fetched.
+    let range = match metadata.burn_after_reading {
+        true => None,
+        false => {
+            headers.insert(header::ACCEPT_RANGES, "bytes".parse().unwrap());
+            requested_range(request, &headers, size)
+        }
+    };
+
+    let range = match range {
+        Some(range) if range.is_empty() => {
+            let content_range = format!("bytes */{size}");
+            headers.insert(header::CONTENT_RANGE, content_range.parse().unwrap());
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+        }
+        Some(range) => range,
+        None => {
+            headers.insert(header::CONTENT_LENGTH, size.into());
+            return Ok((headers, body(data)).into_response());
+        }
+    };
+
+    tokio::io::copy(&mut (&mut data).take(range.start), &mut tokio::io::sink())
+        .await
+        .map_err(|_| Error::StorageError)?;
+
+    let len = range.end - range.start;
+    let content_range = format!("bytes {}-{}/{size}", range.start, range.end - 1);
+    headers.insert(header::CONTENT_RANGE, content_range.parse().unwrap());
+    headers.insert(header::CONTENT_LENGTH, len.into());
+
+    Ok((StatusCode::PARTIAL_CONTENT, headers, body(data.take(len))).into_response())
+}
+
+fn body(data: impl AsyncRead + Send + 'static) -> StreamBody<ReaderStream<impl AsyncRead>> {
+    StreamBody::new(ReaderStream::new(data))
+}
+
+/// Byte range of the paste requested with `Range`, the range is empty if it cannot be
+/// satisfied.
+///
+/// Only a single range is supported, requests for multiple ranges or with an `If-Range`
+/// that does not match the validators in the response `headers` get all of it.
+fn requested_range(request: &HeaderMap, headers: &HeaderMap, size: u64) -> Option<Range<u64>> {
+    // only strong validators match, weak ETags never do
+    if let Some(if_range) = request.get(header::IF_RANGE) {
+        let matches = match if_range.as_bytes().starts_with(b"\"") {
+            true => headers.get(header::ETAG) == Some(if_range),
+            false => {
+                let date = http_date(Some(if_range));
+                date.is_some() && date == http_date(headers.get(header::LAST_MODIFIED))
+            }
+        };
+        if !matches {
+            return None;
+        }
+    }
+
+    let spec = request
+        .get(header::RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes=")?;
+    let (start, end) = spec.trim().split_once('-')?;
+
+    let range = match (start, end) {
+        ("", suffix) => size.saturating_sub(suffix.parse().ok()?)..size,
+        (start, "") => start.parse().ok()?..size,
+        (start, end) => {
+            let (start, end) = (start.parse::<u64>().ok()?, end.parse::<u64>().ok()?);
+            if end < start {
+                return None;
+            }
+            start..end.saturating_add(1).min(size)
+        }
+    };
+
+    Some(range)
+}
+
+fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("text/html"))
+}
 
-    Ok((headers, data))
+/// Encodings listed in `Accept-Encoding`, except the ones explicitly refused with `q=0`.
+fn accepted_encodings(headers: &HeaderMap) -> Vec<Encoding> {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let encoding = parts.next()?.parse().ok()?;
+            let refused = parts.any(
+                |param| matches!(param.strip_prefix("q="), Some(q) if q.parse::<f32>() == Ok(0.0)),
+            );
+            (!refused).then_some(encoding)
+        })
+        .collect()
 }
 
-fn view_paste(theme: &Theme, source: String, ext: Option<String>) -> Result<impl IntoResponse> {
-    let language = ext.map(|ext| Language::from_extension(&ext));
+fn view_paste(theme: &Theme, source: String, ext: &str) -> Html<String> {
+    let (lines, is_escaped) = highlight(theme, &source, ext);
 
-    let highlighted = |highlighted: Highlighted| {
-        let lines = highlighted.lines().collect::<Vec<_>>();
+    let view = templates::View {
+        css: if is_escaped { theme.css() } else { "" },
+        source: &lines.iter().map(String::as_str).collect::<Vec<_>>(),
+        is_escaped,
+    };
+    Html(view.to_string())
+}
+
+/// How [`diff`] shows the changed lines.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffMode {
+    #[default]
+    Unified,
+    Split,
+}
 
-        templates::View {
-            css: theme.css(),
-            source: &lines,
-            is_escaped: true,
+#[derive(Debug, Deserialize)]
+pub struct DiffParams {
+    #[serde(default)]
+    mode: DiffMode,
+}
+
+/// Compares two text pastes line by line, e.g. two revisions of a paste.
+///
+/// Browsers get both pastes highlighted, unified or side by side, everyone else gets
+/// a unified diff. The extension of `b` overrides the ones the pastes were uploaded with.
+pub async fn diff(
+    Path((a, WithExtension(b, ext))): Path<(PasteId, WithExtension<PasteId>)>,
+    Query(params): Query<DiffParams>,
+    headers: HeaderMap,
+    Extension(storage): StorageExtension,
+    Extension(theme): ThemeExtension,
+) -> Result<impl IntoResponse> {
+    let (old, old_ext) = diff_source(&*storage, &a).await?;
+    let (new, new_ext) = diff_source(&*storage, &b).await?;
+    let (a, b) = (a.to_string(), b.to_string());
+
+    let text_diff = similar::TextDiff::from_lines(&old, &new);
+    if !accepts_html(&headers) {
+        return Ok(text_diff
+            .unified_diff()
+            .header(&a, &b)
+            .to_string()
+            .into_response());
+    }
+
+    let ext = ext.or(new_ext).or(old_ext).unwrap_or_default();
+    let (mut old_lines, old_escaped) = highlight(&theme, &old, &ext);
+    let (mut new_lines, new_escaped) = highlight(&theme, &new, &ext);
+    // both sides are rendered alike, even if only one of them could be highlighted
+    if old_escaped != new_escaped {
+        old_lines = highlight(&theme, &old, "").0;
+        new_lines = highlight(&theme, &new, "").0;
+    }
+    let is_escaped = old_escaped && new_escaped;
+
+    let mut rows = Vec::new();
+    for op in text_diff.ops() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+
+        if tag == similar::DiffTag::Equal {
+            rows.extend(
+                old_range
+                    .zip(new_range)
+                    .map(|(old, new)| templates::DiffRow {
+                        old: Some(diff_line(&old_lines, old)),
+                        new: Some(diff_line(&new_lines, new)),
+                        changed: false,
+                    }),
+            );
+        } else if params.mode == DiffMode::Split {
+            let len = old_range.len().max(new_range.len());
+            rows.extend((0..len).map(|offset| {
+                templates::DiffRow {
+                    old: (offset < old_range.len())
+                        .then(|| diff_line(&old_lines, old_range.start + offset)),
+                    new: (offset < new_range.len())
+                        .then(|| diff_line(&new_lines, new_range.start + offset)),
+                    changed: true,
+                }
+            }));
+        } else {
+            rows.extend(old_range.map(|old| templates::DiffRow {
+                old: Some(diff_line(&old_lines, old)),
+                new: None,
+                changed: true,
+            }));
+            rows.extend(new_range.map(|new| templates::DiffRow {
+                old: None,
+                new: Some(diff_line(&new_lines, new)),
+                changed: true,
+            }));
         }
-        .to_string()
+    }
+
+    let page = templates::Diff {
+        css: if is_escaped { theme.css() } else { "" },
+        a: &a,
+        b: &b,
+        split: params.mode == DiffMode::Split,
+        rows: &rows,
+        is_escaped,
     };
-    let html = || {
-        templates::View {
-            source: &source
+    Ok(Html(page.to_string()).into_response())
+}
+
+/// Contents and extension of a paste to compare, pastes which burn after reading are never read.
+async fn diff_source(
+    storage: &(dyn Storage + Send + Sync),
+    id: &PasteId,
+) -> Result<(String, Option<String>)> {
+    let metadata = storage.metadata(id).await.map_err(load_error)?;
+
+    let is_text = matches!(&metadata.content_type, Some(ct) if ct.starts_with("text/"));
+    if !is_text
+        || metadata.burn_after_reading
+        || metadata.client_encrypted
+        || !metadata.files.is_empty()
+    {
+        return Err(Error::NotComparable);
+    }
+
+    let mut paste = load(storage, id, &[]).await?;
+    let source = String::from_utf8(read(&mut paste).await?).map_err(|_| Error::StorageError)?;
+    Ok((source, paste.metadata.extension))
+}
+
+fn diff_line(lines: &[String], index: usize) -> templates::DiffLine<'_> {
+    templates::DiffLine {
+        number: index + 1,
+        source: lines.get(index).map_or("", String::as_str),
+    }
+}
+
+/// Lines of `source` highlighted with the language of `ext`, `true` if they are escaped.
+fn highlight(theme: &Theme, source: &str, ext: &str) -> (Vec<String>, bool) {
+    let highlighted =
+        Language::from_extension(ext).and_then(|language| theme.highlight(language, source));
+
+    match highlighted {
+        Some(highlighted) => (highlighted.lines().map(ToOwned::to_owned).collect(), true),
+        None => {
+            let lines = source
                 .lines()
                 .map(|line| if line.is_empty() { "\n" } else { line })
-                .collect::<Vec<_>>(),
-            ..Default::default()
+                .map(ToOwned::to_owned);
+            (lines.collect(), false)
         }
-        .to_string()
+    }
+}
+
+/// Header carrying the deletion token, in the upload response and for `DELETE` requests.
+const DELETE_TOKEN_HEADER: &str = "X-Delete-Token";
+
+/// Upload options, either passed as query parameters or as multipart fields.
+#[derive(Debug, Default, Deserialize)]
+pub struct UploadOptions {
+    #[serde(default, deserialize_with = "deserialize_expiry")]
+    pub(crate) expires: Option<Expiry>,
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    pub(crate) burn: bool,
+    /// The file was encrypted by the client, the key never reaches the server.
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    pub(crate) encrypted: bool,
+    /// Deletion token of the paste an edit is a revision of, see [`edit`].
+    pub(crate) token: Option<String>,
+}
+
+/// Parses a boolean option, e.g. `on` from a HTML checkbox or `true`/`1` from a query.
+fn parse_flag(value: &str) -> bool {
+    !matches!(value, "" | "0" | "false" | "off" | "no")
+}
+
+/// Parses an optional expiry, empty values, e.g. from a HTML select, are none.
+fn deserialize_expiry<'de, D>(deserializer: D) -> Result<Option<Expiry>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_flag<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    String::deserialize(deserializer).map(|value| parse_flag(&value))
+}
+
+/// Text submitted with an URL encoded form, e.g. by a plain HTML form.
+#[derive(Debug, Deserialize)]
+struct TextForm {
+    content: String,
+    /// Extension used to highlight the paste, e.g. `rs`.
+    lang: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_expiry")]
+    expires: Option<Expiry>,
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    burn: bool,
+}
+
+/// Upload being read, from a multipart field, a form or the request body.
+pub(crate) type Upload = UploadReader<Box<dyn AsyncRead + Send + Unpin>>;
+
+/// A file which is being uploaded.
+pub(crate) struct NewPaste {
+    pub file_name: Option<String>,
+    /// Extension requested for the paste, preferred to the one of the file name.
+    /// Restricted to the characters allowed by [`sanitize_extension`].
+    pub lang: Option<String>,
+    pub upload: Upload,
+    /// Further files of a multipart upload, read once `upload` is complete.
+    pub rest: Option<multer::Multipart<'static>>,
+}
+
+/// Starts reading an uploaded file, `None` if it is empty.
+pub(crate) async fn upload_reader(
+    reader: impl AsyncRead + Send + Unpin + 'static,
+    options: &UploadOptions,
+    config: &Config,
+) -> Result<Option<Upload>> {
+    let reader: Box<dyn AsyncRead + Send + Unpin> = Box::new(reader);
+
+    if options.encrypted {
+        // the kind of ciphertext is unknown, it may be either
+        let limit = config.max_text_size.max(config.max_image_size);
+        UploadReader::opaque(reader, limit).await
+    } else {
+        UploadReader::new(reader, |kind| match kind {
+            FileKind::Binary(_) => config.max_image_size,
+            FileKind::Text(_) => config.max_text_size,
+        })
+        .await
+    }
+}
+
+/// Reads a body into memory, fails if it is larger than `limit`.
+pub(crate) async fn read_body(body: BodyStream, limit: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    StreamReader::new(body.map_err(io::Error::other))
+        .take(limit + 1)
+        .read_to_end(&mut data)
+        .await
+        .map_err(|_| Error::BadRequest)?;
+
+    if data.len() as u64 > limit {
+        return Err(Error::PayloadTooLarge(limit));
+    }
+    Ok(data)
+}
+
+/// Starts reading the file of an upload, options sent along with it are added to `options`.
+///
+/// The file is either the `file` field of a multipart form, options and its `lang` sent as
+/// fields have to precede it and further `file` fields make a paste of several files,
+/// the `content` of an URL encoded form with an optional `lang`, or the whole request body,
+/// which is named `file_name`.
+pub(crate) async fn read_paste(
+    body: UploadBody,
+    file_name: Option<String>,
+    options: &mut UploadOptions,
+    config: &Config,
+) -> Result<NewPaste> {
+    match body {
+        UploadBody::Multipart(data) => multipart_paste(data, options, config).await,
+        UploadBody::Form(body) => form_paste(body, options, config).await,
+        UploadBody::Json(body) | UploadBody::Raw(body) => {
+            let reader = StreamReader::new(body.map_err(io::Error::other));
+            let upload = upload_reader(reader, options, config)
+                .await?
+                .ok_or(Error::MissingFile)?;
+            Ok(NewPaste {
+                file_name,
+                lang: None,
+                upload,
+                rest: None,
+            })
+        }
+    }
+}
+
+async fn multipart_paste(
+    mut data: multer::Multipart<'static>,
+    options: &mut UploadOptions,
+    config: &Config,
+) -> Result<NewPaste> {
+    let mut lang = None;
+
+    loop {
+        let field = data
+            .next_field()
+            .await
+            .map_err(multipart_error)?
+            .ok_or(Error::MissingFile)?;
+
+        match field.name() {
+            Some("file") => {
+                let file_name = field.file_name().map(ToOwned::to_owned);
+
+                let reader = StreamReader::new(field.map_err(io::Error::other));
+                // empty fields, e.g. the text area when a file is uploaded
+                if let Some(upload) = upload_reader(reader, options, config).await? {
+                    // client side encrypted uploads are a single file
+                    return Ok(NewPaste {
+                        file_name,
+                        lang: lang.as_deref().and_then(sanitize_extension),
+                        upload,
+                        rest: (!options.encrypted).then_some(data),
+                    });
+                }
+            }
+            Some("expires") => {
+                let expires = field.text().await.map_err(multipart_error)?;
+                if !expires.is_empty() {
+                    options.expires = Some(expires.parse().map_err(|_| Error::BadRequest)?);
+                }
+            }
+            Some("burn") => {
+                options.burn = parse_flag(&field.text().await.map_err(multipart_error)?);
+            }
+            Some("encrypted") => {
+                options.encrypted = parse_flag(&field.text().await.map_err(multipart_error)?);
+            }
+            Some("lang") => {
+                lang = Some(field.text().await.map_err(multipart_error)?);
+            }
+            Some("token") => {
+                options.token = Some(field.text().await.map_err(multipart_error)?);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn multipart_error(err: multer::Error) -> Error {
+    match err {
+        multer::Error::FieldSizeExceeded { limit, .. }
+        | multer::Error::StreamSizeExceeded { limit } => Error::PayloadTooLarge(limit),
+        _ => Error::BadRequest,
+    }
+}
+
+/// Next non-empty `file` field of a multipart upload, other fields are skipped.
+async fn next_file(
+    data: &mut multer::Multipart<'static>,
+    config: &Config,
+) -> Result<Option<(Option<String>, Upload)>> {
+    while let Some(field) = data.next_field().await.map_err(multipart_error)? {
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        let file_name = field.file_name().map(ToOwned::to_owned);
+        let reader = StreamReader::new(field.map_err(io::Error::other));
+        if let Some(upload) = upload_reader(reader, &UploadOptions::default(), config).await? {
+            return Ok(Some((file_name, upload)));
+        }
+    }
+    Ok(None)
+}
+
+async fn form_paste(
+    body: BodyStream,
+    options: &mut UploadOptions,
+    config: &Config,
+) -> Result<NewPaste> {
+    let reader = StreamReader::new(body.map_err(io::Error::other));
+
+    let limit = config.max_text_size.max(config.max_image_size);
+    let mut data = Vec::new();
+    reader
+        .take(limit + 1)
+        .read_to_end(&mut data)
+        .await
+        .map_err(|_| Error::BadRequest)?;
+    if data.len() as u64 > limit {
+        return Err(Error::PayloadTooLarge(limit));
+    }
+
+    let form = serde_urlencoded::from_bytes::<TextForm>(&data).map_err(|_| Error::BadRequest)?;
+    options.expires = form.expires.or(options.expires);
+    options.burn |= form.burn;
+
+    let content = io::Cursor::new(form.content.into_bytes());
+    let upload = upload_reader(content, options, config)
+        .await?
+        .ok_or(Error::Empty)?;
+    Ok(NewPaste {
+        file_name: None,
+        lang: form.lang.as_deref().and_then(sanitize_extension),
+        upload,
+        rest: None,
+    })
+}
+
+/// Saves an upload, returns its ID and the metadata it was saved with.
+///
+/// A revision of an `original` paste shares its deletion token, it is linked to it
+/// by the caller.
+pub(crate) async fn save_paste(
+    storage: &(dyn Storage + Send + Sync),
+    config: &Config,
+    uploader: Option<IpAddr>,
+    options: &UploadOptions,
+    paste: NewPaste,
+    original: Option<(&PasteId, &PasteMetadata)>,
+) -> Result<(PasteId, PasteMetadata)> {
+    let NewPaste {
+        file_name,
+        lang,
+        upload,
+        rest,
+    } = paste;
+
+    let (file_name, extension, content_type) = match upload.kind() {
+        Some(kind) => {
+            let extension =
+                upload_extension(lang.as_deref(), file_name.as_deref(), kind, upload.head());
+            (file_name, extension, kind.content_type())
+        }
+        // Ciphertext, nothing can be inferred and the file name is not kept.
+        None => (None, None, "application/octet-stream"),
+    };
+
+    let now = unix_now();
+    let mut metadata = PasteMetadata {
+        file_name,
+        extension,
+        content_type: Some(content_type.to_owned()),
+        created_at: now,
+        size: 0,
+        uploader,
+        expires_at: config.expiry(options.expires).expires_at(now),
+        burn_after_reading: options.burn,
+        delete_token: match original {
+            Some((_, original)) => original.delete_token.clone(),
+            None => Some(generate_token()),
+        },
+        encoding: None,
+        encrypted: false,
+        client_encrypted: options.encrypted,
+        digest: None,
+        content_digest: None,
+        files: Vec::new(),
+        revision_of: original.map(|(id, _)| id.to_string()),
+    };
+
+    let data = match rest {
+        Some(rest) => {
+            let first = bundle_file(
+                metadata.file_name.as_deref(),
+                metadata.extension.clone(),
+                content_type,
+                &[],
+            );
+            let files = Arc::default();
+            let reader = bundle_reader(
+                first,
+                upload,
+                rest,
+                config.clone(),
+                options.burn,
+                Arc::clone(&files),
+            );
+            PasteData::new(reader).with_files(files)
+        }
+        None => PasteData::new(upload),
+    };
+
+    let progress = data.progress();
+    let id = storage
+        .save_stream(data, metadata.clone())
+        .await
+        .map_err(save_error)?;
+
+    // the storage completed its own copy of the metadata
+    progress.finish(&mut metadata);
+    Ok((id, metadata))
+}
+
+/// Extension used to highlight an upload, `lang` is preferred to well-known file names and
+/// the extension of the file name. Text without any is detected from its `head`, before
+/// falling back to the sniffed kind.
+fn upload_extension(
+    lang: Option<&str>,
+    file_name: Option<&str>,
+    kind: FileKind,
+    head: &[u8],
+) -> Option<String> {
+    let known = file_name.and_then(detect::from_file_name);
+    let ext = file_name
+        .and_then(|f| f.rsplit_once('.'))
+        .and_then(|(_, ext)| sanitize_extension(ext));
+
+    let detected = || match kind {
+        FileKind::Text(_) => detect::from_content(head).map(|lang| lang.extension()),
+        FileKind::Binary(_) => None,
     };
 
-    let response = match language {
-        Some(Some(language)) => {
-            let v = theme
-                .highlight(language, &source)
-                .map(highlighted)
-                .unwrap_or_else(html);
-            Html(v).into_response()
+    lang.or(known.map(|lang| lang.extension()))
+        .or(ext.as_deref())
+        .filter(|ext| !ext.is_empty())
+        .map(|x| x.to_lowercase())
+        .or_else(|| detected().map(ToOwned::to_owned))
+        .or_else(|| kind.extension().map(ToOwned::to_owned))
+}
+
+/// Lowercase extension, `None` unless it only consists of `[a-z0-9+_-]`.
+///
+/// Extensions end up in URLs and headers like `Location`, which must not contain anything else.
+pub(crate) fn sanitize_extension(ext: &str) -> Option<String> {
+    let ext = ext.to_lowercase();
+    let valid = !ext.is_empty()
+        && ext
+            .bytes()
+            .all(|b| matches!(b, b'a'..=b'z' | b'0'..=b'9' | b'+' | b'_' | b'-'));
+    valid.then_some(ext)
+}
+
+/// Entry of a new file of a paste holding several `files`, its size and digest are added
+/// while it is read.
+fn bundle_file(
+    file_name: Option<&str>,
+    extension: Option<String>,
+    content_type: &str,
+    files: &[PasteFile],
+) -> PasteFile {
+    // only the last component of a path, restricted to characters safe in URLs
+    let name = file_name
+        .and_then(|name| name.rsplit(['/', '\\']).next())
+        .unwrap_or_default()
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                true => c,
+                false => '_',
+            },
+        )
+        .take(64)
+        .collect::<String>();
+
+    let name = match name.trim_start_matches('.') {
+        "" => match &extension {
+            Some(ext) => format!("file.{ext}"),
+            None => "file".to_owned(),
+        },
+        name => name.to_owned(),
+    };
+
+    // routes of the paste itself
+    let is_taken = |name: &str| {
+        matches!(name, "raw" | "rev" | "delete" | "edit" | "fork" | "history")
+            || files.iter().any(|file| file.name == name)
+    };
+    let name = match is_taken(&name) {
+        false => name,
+        true => {
+            let (stem, ext) = name.split_once('.').unwrap_or((&name, ""));
+            (2..)
+                .map(|n| match ext {
+                    "" => format!("{stem}-{n}"),
+                    ext => format!("{stem}-{n}.{ext}"),
+                })
+                .find(|name| !is_taken(name))
+                .unwrap()
         }
-        Some(None) => Html(html()).into_response(),
-        None => source.into_response(),
     };
 
-    Ok(response)
+    PasteFile {
+        name,
+        extension,
+        content_type: content_type.to_owned(),
+        size: 0,
+        digest: String::new(),
+    }
+}
+
+struct BundleState {
+    /// File which is being read, with its entry and the hasher of its contents.
+    current: Option<(PasteFile, Sha256, Upload)>,
+    rest: Option<multer::Multipart<'static>>,
+    files: Arc<Mutex<Vec<PasteFile>>>,
+    config: Config,
+    size: u64,
+}
+
+/// Reads the files of a multipart upload one after another, starting with the `first` one
+/// which is read from `upload`. Each file is added to `files` once it is complete.
+///
+/// All files together are limited like a single file of either kind. Uploads which `burn`
+/// after reading fail with a second file, the page showing the files would burn them.
+fn bundle_reader(
+    first: PasteFile,
+    upload: Upload,
+    rest: multer::Multipart<'static>,
+    config: Config,
+    burn: bool,
+    files: Arc<Mutex<Vec<PasteFile>>>,
+) -> impl AsyncRead + Send + Unpin {
+    const CHUNK_LEN: usize = 64 * 1024;
+
+    let limit = config.max_text_size.max(config.max_image_size);
+    let invalid = |err: Error| io::Error::new(io::ErrorKind::InvalidData, err);
+
+    let state = BundleState {
+        current: Some((first, Sha256::new(), upload)),
+        rest: Some(rest),
+        files,
+        config,
+        size: 0,
+    };
+
+    let chunks = futures_util::stream::unfold(state, move |mut state| async move {
+        loop {
+            if let Some((file, hasher, upload)) = &mut state.current {
+                let mut chunk = vec![0; CHUNK_LEN];
+                let read = match upload.read(&mut chunk).await {
+                    Ok(read) => read,
+                    Err(err) => {
+                        state.rest = None;
+                        state.current = None;
+                        return Some((Err(err), state));
+                    }
+                };
+
+                if read == 0 {
+                    let (file, hasher, _) = state.current.take().unwrap();
+                    let digest = hex::encode(hasher.finalize());
+                    state
+                        .files
+                        .lock()
+                        .unwrap()
+                        .push(PasteFile { digest, ..file });
+                    continue;
+                }
+
+                chunk.truncate(read);
+                file.size += read as u64;
+                hasher.update(&chunk);
+
+                state.size += read as u64;
+                if state.size > limit {
+                    state.rest = None;
+                    state.current = None;
+                    return Some((Err(invalid(Error::PayloadTooLarge(limit))), state));
+                }
+                return Some((Ok(Bytes::from(chunk)), state));
+            }
+
+            let rest = state.rest.as_mut()?;
+            match next_file(rest, &state.config).await {
+                Ok(Some(_)) if burn => {
+                    state.rest = None;
+                    return Some((Err(invalid(Error::BurnSeveralFiles)), state));
+                }
+                Ok(Some((file_name, upload))) => {
+                    let (extension, content_type) = match upload.kind() {
+                        Some(kind) => (
+                            upload_extension(None, file_name.as_deref(), kind, upload.head()),
+                            kind.content_type(),
+                        ),
+                        None => (None, "application/octet-stream"),
+                    };
+                    let file = bundle_file(
+                        file_name.as_deref(),
+                        extension,
+                        content_type,
+                        &state.files.lock().unwrap(),
+                    );
+                    state.current = Some((file, Sha256::new(), upload));
+                }
+                Ok(None) => return None,
+                Err(err) => {
+                    state.rest = None;
+                    return Some((Err(invalid(err)), state));
+                }
+            }
+        }
+    });
+
+    StreamReader::new(Box::pin(chunks))
 }
 
+/// Uploads a paste, the file is streamed to the storage.
+///
+/// See [`read_paste`] for the supported bodies, e.g. `curl -T file https://host/file.rs`.
+/// Raw bodies are named by the path they are sent to.
+#[allow(clippy::too_many_arguments)]
 pub async fn upload(
+    path: Option<Path<String>>,
     Protocol(protocol): Protocol,
     Host(host): Host,
-    ContentLengthLimit(mut data): ContentLengthLimit<Multipart, MAX_FILE_SIZE>,
+    ClientIp(uploader): ClientIp,
+    Query(mut options): Query<UploadOptions>,
+    body: UploadBody,
     Extension(storage): StorageExtension,
+    Extension(config): ConfigExtension,
 ) -> Result<impl IntoResponse> {
-    while let Some(field) = data.next_field().await.map_err(|_| Error::BadRequest)? {
-        if field.name() == Some("file") {
-            let file_ext = field
-                .file_name()
-                .and_then(|f| f.rsplit('.').next())
-                .filter(|ext| !ext.is_empty())
-                .map(|x| x.to_lowercase());
-
-            let data = field.bytes().await.map_err(|_| Error::BadRequest)?;
-            if data.is_empty() {
-                continue;
+    let file_name = path.map(|Path(file_name)| file_name);
+    let paste = read_paste(body, file_name, &mut options, &config).await?;
+
+    let (id, metadata) = save_paste(&*storage, &config, uploader, &options, paste, None).await?;
+    let path = match &metadata.extension {
+        Some(ext) => format!("{id}.{ext}"),
+        None => id.to_string(),
+    };
+
+    let base = format!("{protocol}://{host}");
+    let url = format!("{base}/{path}");
+    Ok(upload_response(
+        &base,
+        &path,
+        &url,
+        (&id, &id),
+        &metadata,
+        &options,
+    ))
+}
+
+/// Uploads a new revision of a paste, accepts the same bodies as [`upload`].
+///
+/// The revision is linked to the original paste and authorized with its deletion token,
+/// passed like for [`delete`] or as the `token` option, e.g. by the form of [`edit_form`].
+#[allow(clippy::too_many_arguments)]
+pub async fn edit(
+    Path(WithExtension(id, _)): Path<WithExtension<PasteId>>,
+    Protocol(protocol): Protocol,
+    Host(host): Host,
+    ClientIp(uploader): ClientIp,
+    Query(mut options): Query<UploadOptions>,
+    headers: HeaderMap,
+    body: UploadBody,
+    Extension(storage): StorageExtension,
+    Extension(config): ConfigExtension,
+) -> Result<impl IntoResponse> {
+    let paste = read_paste(body, None, &mut options, &config).await?;
+
+    let token = options
+        .token
+        .clone()
+        .or_else(|| header_token(&headers))
+        .ok_or(Error::InvalidToken)?;
+    let (original, metadata) = original(&*storage, &id).await?;
+    if !metadata.is_delete_token(&token) {
+        return Err(Error::InvalidToken);
+    }
+
+    let parent = Some((&original, &metadata));
+    let (id, metadata) = save_paste(&*storage, &config, uploader, &options, paste, parent).await?;
+    let revision = storage
+        .add_revision(&original, &id)
+        .await
+        .map_err(|_| Error::StorageError)?;
+
+    let base = format!("{protocol}://{host}");
+    let path = format!("/{original}/rev/{revision}");
+    let url = format!("{base}{path}");
+    Ok(upload_response(
+        &base,
+        &path,
+        &url,
+        (&id, &original),
+        &metadata,
+        &options,
+    ))
+}
+
+/// Redirects to a new paste at `location`, the body lists its `url` and the URLs of its
+/// files, followed by the URLs to edit the `original` and to delete the paste.
+fn upload_response(
+    base: &str,
+    location: &str,
+    url: &str,
+    (id, original): (&PasteId, &PasteId),
+    metadata: &PasteMetadata,
+    options: &UploadOptions,
+) -> Response {
+    // always set on new pastes
+    let delete_token = metadata.delete_token.as_deref().unwrap_or_default();
+
+    let mut body = format!("{url}\n");
+    for file in &metadata.files {
+        body += &format!("  {base}/{id}/{}\n", file.name);
+    }
+    body += &format!(
+        "edit: {base}/{original}/edit?token={delete_token}\n\
+         delete: {base}/{id}/delete?token={delete_token}\n"
+    );
+
+    // Following a redirect would immediately burn the paste,
+    // encrypted uploads need the URL to append the key.
+    let status = if options.burn || options.encrypted {
+        201
+    } else {
+        303
+    };
+
+    Response::builder()
+        .status(status)
+        .header("Location", location)
+        .header(DELETE_TOKEN_HEADER, delete_token)
+        .body(body)
+        .unwrap()
+        .into_response()
+}
+
+/// The upload form to edit a paste, prefilled with it if it is a single text file.
+pub async fn edit_form(
+    Path(WithExtension(id, _)): Path<WithExtension<PasteId>>,
+    Query(params): Query<TokenParams>,
+    headers: HeaderMap,
+    Extension(storage): StorageExtension,
+) -> Result<impl IntoResponse> {
+    let token = params
+        .token
+        .or_else(|| header_token(&headers))
+        .ok_or(Error::InvalidToken)?;
+    let (original, metadata) = original(&*storage, &id).await?;
+    if !metadata.is_delete_token(&token) {
+        return Err(Error::InvalidToken);
+    }
+
+    let (content, lang) = prefill(&*storage, &id).await?;
+    let page = templates::Index {
+        action: &format!("/{original}/edit"),
+        content: &content,
+        lang: &lang,
+        token: &token,
+    };
+    Ok(Html(page.to_string()))
+}
+
+/// The upload form, prefilled with a paste to start a new one from.
+pub async fn fork(
+    Path(WithExtension(id, _)): Path<WithExtension<PasteId>>,
+    Extension(storage): StorageExtension,
+) -> Result<impl IntoResponse> {
+    let (content, lang) = prefill(&*storage, &id).await?;
+    let page = templates::Index {
+        action: "/",
+        content: &content,
+        lang: &lang,
+        ..Default::default()
+    };
+    Ok(Html(page.to_string()))
+}
+
+/// Contents and extension to prefill the upload form with, the contents are empty unless
+/// the paste is a single text file. Pastes which burn after reading are never read.
+async fn prefill(storage: &(dyn Storage + Send + Sync), id: &PasteId) -> Result<(String, String)> {
+    let metadata = storage.metadata(id).await.map_err(load_error)?;
+    let lang = metadata.extension.clone().unwrap_or_default();
+
+    let is_text = matches!(&metadata.content_type, Some(ct) if ct.starts_with("text/"));
+    if !is_text
+        || metadata.burn_after_reading
+        || metadata.client_encrypted
+        || !metadata.files.is_empty()
+    {
+        return Ok((String::new(), lang));
+    }
+
+    let mut paste = load(storage, id, &[]).await?;
+    let content = String::from_utf8(read(&mut paste).await?).map_err(|_| Error::StorageError)?;
+    Ok((content, lang))
+}
+
+/// Errors of the upload itself are passed through, e.g. invalid UTF-8.
+fn save_error(err: storage::SaveError) -> Error {
+    match err {
+        storage::SaveError::Read(err) => err
+            .into_inner()
+            .and_then(|err| err.downcast::<Error>().ok())
+            .map(|err| *err)
+            .unwrap_or(Error::BadRequest),
+        _ => Error::StorageError,
+    }
+}
+
+pub(crate) fn load_error(err: storage::LoadError) -> Error {
+    match err {
+        storage::LoadError::NotFound => Error::NotFound,
+        storage::LoadError::Expired => Error::Gone,
+        _ => Error::StorageError,
+    }
+}
+
+/// Deletion token of a paste, which also authorizes edits.
+#[derive(Debug, Deserialize)]
+pub struct TokenParams {
+    token: Option<String>,
+}
+
+fn header_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(DELETE_TOKEN_HEADER)
+        .and_then(|token| token.to_str().ok())
+        .map(ToOwned::to_owned)
+}
+
+pub async fn delete(
+    Path(WithExtension(id, _)): Path<WithExtension<PasteId>>,
+    Query(params): Query<TokenParams>,
+    headers: HeaderMap,
+    Extension(storage): StorageExtension,
+) -> Result<impl IntoResponse> {
+    let token = params
+        .token
+        .or_else(|| header_token(&headers))
+        .ok_or(Error::InvalidToken)?;
+
+    storage.delete(&id, &token).await.map_err(|e| match e {
+        storage::DeleteError::NotFound => Error::NotFound,
+        storage::DeleteError::InvalidToken => Error::InvalidToken,
+        _ => Error::StorageError,
+    })?;
+
+    Ok("deleted\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETAG: &str = "\"abc\"";
+    const LAST_MODIFIED: &str = "Sun, 18 Oct 2026 10:00:00 GMT";
+
+    fn range(request: &[(header::HeaderName, &str)], size: u64) -> Option<Range<u64>> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ETAG, ETAG.parse().unwrap());
+        headers.insert(header::LAST_MODIFIED, LAST_MODIFIED.parse().unwrap());
+
+        let request = request
+            .iter()
+            .map(|(name, value)| (name.clone(), value.parse().unwrap()))
+            .collect();
+        requested_range(&request, &headers, size)
+    }
+
+    fn bytes(spec: &str, size: u64) -> Option<Range<u64>> {
+        range(&[(header::RANGE, spec)], size)
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(bytes("bytes=0-4", 10), Some(0..5));
+        assert_eq!(bytes("bytes=5-", 10), Some(5..10));
+        // the end is capped at the size
+        assert_eq!(bytes("bytes=5-100", 10), Some(5..10));
+        assert_eq!(bytes("bytes=9-9", 10), Some(9..10));
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(bytes("bytes=-3", 10), Some(7..10));
+        assert_eq!(bytes("bytes=-100", 10), Some(0..10));
+        assert!(bytes("bytes=-0", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        // answered with 416
+        assert!(bytes("bytes=10-", 10).unwrap().is_empty());
+        assert!(bytes("bytes=10-20", 10).unwrap().is_empty());
+        assert!(bytes("bytes=0-0", 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn ignored_ranges() {
+        // answered with all of it
+        assert_eq!(bytes("bytes=5-4", 10), None);
+        assert_eq!(bytes("bytes=0-1,3-4", 10), None);
+        assert_eq!(bytes("items=0-4", 10), None);
+        assert_eq!(bytes("bytes=a-b", 10), None);
+        assert_eq!(range(&[], 10), None);
+    }
+
+    #[test]
+    fn if_range() {
+        let matching = [
+            [(header::RANGE, "bytes=0-4"), (header::IF_RANGE, ETAG)],
+            [
+                (header::RANGE, "bytes=0-4"),
+                (header::IF_RANGE, LAST_MODIFIED),
+            ],
+        ];
+        for request in &matching {
+            assert_eq!(range(request, 10), Some(0..5));
+        }
+
+        let mismatching = [
+            [
+                (header::RANGE, "bytes=0-4"),
+                (header::IF_RANGE, "\"other\""),
+            ],
+            [
+                (header::RANGE, "bytes=0-4"),
+                (header::IF_RANGE, "W/\"abc\""),
+            ],
+            [
+                (header::RANGE, "bytes=0-4"),
+                (header::IF_RANGE, "Sun, 18 Oct 2026 09:59:59 GMT"),
+            ],
+            [
+                (header::RANGE, "bytes=0-4"),
+                (header::IF_RANGE, "yesterday"),
+            ],
+        ];
+        for request in &mismatching {
+            assert_eq!(range(request, 10), None);
+        }
+    }
+
+    #[test]
+    fn cache_control() {
+        let cache_control = |metadata: &PasteMetadata| {
+            cache_headers(metadata, None)[header::CACHE_CONTROL]
+                .to_str()
+                .unwrap()
+                .to_owned()
+        };
+
+        let metadata = PasteMetadata::default();
+        assert_eq!(cache_control(&metadata), "public, max-age=31536000");
+
+        let metadata = PasteMetadata {
+            expires_at: Some(unix_now() + 60),
+            ..PasteMetadata::default()
+        };
+        let max_age: u64 = cache_control(&metadata)
+            .strip_prefix("public, max-age=")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((59..=60).contains(&max_age));
+
+        let metadata = PasteMetadata {
+            expires_at: Some(unix_now() - 60),
+            ..PasteMetadata::default()
+        };
+        assert_eq!(cache_control(&metadata), "public, max-age=0");
+
+        let metadata = PasteMetadata {
+            burn_after_reading: true,
+            ..PasteMetadata::default()
+        };
+        assert_eq!(cache_control(&metadata), "no-store");
+    }
+
+    #[tokio::test]
+    async fn head_does_not_burn() {
+        let storage: Arc<dyn Storage + Send + Sync> =
+            Arc::new(storage::MemoryStorage::new(crate::RandomIdGen::new(8)));
+        let theme: Theme = serde_json::from_str(include_str!("../themes/default.json")).unwrap();
+        let theme = Arc::new(theme);
+
+        let metadata = PasteMetadata {
+            content_type: Some("text/plain; charset=utf-8".to_owned()),
+            extension: Some("rs".to_owned()),
+            burn_after_reading: true,
+            ..PasteMetadata::default()
+        };
+        let id = storage.save("fn main() {}".into(), metadata).await.unwrap();
+
+        let mut browser = HeaderMap::new();
+        browser.insert(header::ACCEPT, "text/html".parse().unwrap());
+
+        let response = view(
+            Path(WithExtension(id.clone(), None)),
+            Method::HEAD,
+            browser,
+            Extension(Arc::clone(&storage)),
+            Extension(Arc::clone(&theme)),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+
+        let response = raw(
+            Path(WithExtension(id.clone(), None)),
+            Method::HEAD,
+            HeaderMap::new(),
+            Extension(Arc::clone(&storage)),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "12");
+        assert!(response.headers().get(header::ACCEPT_RANGES).is_none());
+
+        // still there for the one GET
+        let response = raw(
+            Path(WithExtension(id.clone(), None)),
+            Method::GET,
+            HeaderMap::new(),
+            Extension(Arc::clone(&storage)),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(matches!(
+            storage.metadata(&id).await,
+            Err(storage::LoadError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn accept_encoding() {
+        let accepted = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT_ENCODING, value.parse().unwrap());
+            accepted_encodings(&headers)
+        };
+
+        assert_eq!(accepted("gzip, deflate, br"), [Encoding::Gzip]);
+        assert_eq!(
+            accepted("zstd;q=0.5, gzip"),
+            [Encoding::Zstd, Encoding::Gzip]
+        );
+        assert_eq!(accepted("gzip;q=0, zstd"), [Encoding::Zstd]);
+        assert!(accepted("identity").is_empty());
+        assert!(accepted_encodings(&HeaderMap::new()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn compressed_only_if_accepted() {
+        let inner: Arc<dyn Storage + Send + Sync> =
+            Arc::new(storage::MemoryStorage::new(crate::RandomIdGen::new(8)));
+        let storage: Arc<dyn Storage + Send + Sync> =
+            Arc::new(storage::CompressedStorage::new(inner, Some(Encoding::Gzip)));
+
+        let metadata = PasteMetadata {
+            content_type: Some("text/plain; charset=utf-8".to_owned()),
+            ..PasteMetadata::default()
+        };
+        let id = storage.save("hello world".into(), metadata).await.unwrap();
+
+        let response = |accept_encoding: Option<&'static str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(value) = accept_encoding {
+                headers.insert(header::ACCEPT_ENCODING, value.parse().unwrap());
             }
+            raw(
+                Path(WithExtension(id.clone(), None)),
+                Method::GET,
+                headers,
+                Extension(Arc::clone(&storage)),
+            )
+        };
 
-            let guess_ext = File::infer(&data)?.extension();
+        let gzip = response(Some("gzip")).await.unwrap().into_response();
+        assert_eq!(gzip.headers()[header::CONTENT_ENCODING], "gzip");
+        assert!(gzip.headers().get(header::CONTENT_LENGTH).is_none());
 
-            let id = storage.save(data).await.map_err(|_| Error::StorageError)?;
-            let path = file_ext
-                .as_deref()
-                .or(guess_ext)
-                .map(|ext| format!("{id}.{ext}"))
-                .unwrap_or_else(|| id.into());
+        for accept_encoding in [None, Some("zstd"), Some("gzip;q=0")] {
+            let plain = response(accept_encoding).await.unwrap().into_response();
+            assert!(plain.headers().get(header::CONTENT_ENCODING).is_none());
+            assert_eq!(plain.headers()[header::CONTENT_LENGTH], "11");
 
-            let response = Response::builder()
-                .status(303)
-                .header("Location", &path)
-                .body(format!("{protocol}://{host}/{path}\n"))
-                .unwrap();
-            return Ok(response);
+            let body = hyper::body::to_bytes(plain.into_body()).await.unwrap();
+            assert_eq!(body, "hello world");
         }
     }
 
-    Err(Error::MissingFile)
+    fn is_not_modified(request: &[(header::HeaderName, &str)]) -> bool {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ETAG, ETAG.parse().unwrap());
+        headers.insert(header::LAST_MODIFIED, LAST_MODIFIED.parse().unwrap());
+
+        let request = request
+            .iter()
+            .map(|(name, value)| (name.clone(), value.parse().unwrap()))
+            .collect();
+        match not_modified(&request, headers) {
+            Some(response) => {
+                assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+                assert_eq!(response.headers()[header::ETAG], ETAG);
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[test]
+    fn if_none_match() {
+        assert!(is_not_modified(&[(header::IF_NONE_MATCH, ETAG)]));
+        assert!(is_not_modified(&[(
+            header::IF_NONE_MATCH,
+            "\"x\", \"abc\""
+        )]));
+        assert!(is_not_modified(&[(header::IF_NONE_MATCH, "*")]));
+        assert!(!is_not_modified(&[(header::IF_NONE_MATCH, "\"other\"")]));
+        assert!(!is_not_modified(&[(header::IF_NONE_MATCH, "\"abc-html\"")]));
+        assert!(!is_not_modified(&[]));
+    }
+
+    #[test]
+    fn if_none_match_weak() {
+        // compared weakly, unlike for `If-Range`
+        assert!(is_not_modified(&[(header::IF_NONE_MATCH, "W/\"abc\"")]));
+        assert!(!is_not_modified(&[(header::IF_NONE_MATCH, "W/\"other\"")]));
+    }
+
+    #[test]
+    fn if_modified_since() {
+        assert!(is_not_modified(&[(
+            header::IF_MODIFIED_SINCE,
+            LAST_MODIFIED
+        )]));
+        assert!(is_not_modified(&[(
+            header::IF_MODIFIED_SINCE,
+            "Sun, 18 Oct 2026 10:00:01 GMT"
+        )]));
+        assert!(!is_not_modified(&[(
+            header::IF_MODIFIED_SINCE,
+            "Sun, 18 Oct 2026 09:59:59 GMT"
+        )]));
+        assert!(!is_not_modified(&[(
+            header::IF_MODIFIED_SINCE,
+            "yesterday"
+        )]));
+
+        // ignored with `If-None-Match`
+        assert!(!is_not_modified(&[
+            (header::IF_NONE_MATCH, "\"other\""),
+            (header::IF_MODIFIED_SINCE, LAST_MODIFIED),
+        ]));
+        assert!(is_not_modified(&[
+            (header::IF_NONE_MATCH, ETAG),
+            (header::IF_MODIFIED_SINCE, "Sun, 18 Oct 2026 09:59:59 GMT"),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn delete_requires_the_token() {
+        use axum::{body::Body, routing, Router};
+        use hyper::Request;
+        use tower::ServiceExt;
+
+        let storage: Arc<dyn Storage + Send + Sync> =
+            Arc::new(storage::MemoryStorage::new(crate::RandomIdGen::new(8)));
+        let app = Router::new()
+            .route("/:id", routing::delete(delete))
+            .route("/:id/delete", routing::get(delete))
+            .layer(Extension(Arc::clone(&storage)));
+
+        let metadata = PasteMetadata {
+            delete_token: Some("secret".to_owned()),
+            ..PasteMetadata::default()
+        };
+        let id = storage.save("a".into(), metadata).await.unwrap();
+
+        let status = |request: Request<Body>| {
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+        let by_header = |token: Option<&str>| {
+            let request = Request::delete(format!("/{id}"));
+            match token {
+                Some(token) => request.header(DELETE_TOKEN_HEADER, token),
+                None => request,
+            }
+            .body(Body::empty())
+            .unwrap()
+        };
+        let by_query = |query: &str| {
+            Request::get(format!("/{id}/delete{query}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for request in [
+            by_header(None),
+            by_header(Some("wrong")),
+            by_header(Some("secre")),
+            by_header(Some("")),
+            by_query(""),
+            by_query("?token=wrong"),
+            by_query("?token=secrets"),
+            by_query("?token="),
+        ] {
+            assert_eq!(status(request).await, StatusCode::FORBIDDEN);
+        }
+        assert!(storage.metadata(&id).await.is_ok());
+
+        assert_eq!(status(by_query("?token=secret")).await, StatusCode::OK);
+        assert!(storage.metadata(&id).await.is_err());
+        assert_eq!(
+            status(by_query("?token=secret")).await,
+            StatusCode::NOT_FOUND
+        );
+
+        let metadata = PasteMetadata {
+            delete_token: Some("secret".to_owned()),
+            ..PasteMetadata::default()
+        };
+        let id = storage.save("b".into(), metadata).await.unwrap();
+        let request = Request::delete(format!("/{id}"))
+            .header(DELETE_TOKEN_HEADER, "secret")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(status(request).await, StatusCode::OK);
+        assert!(storage.metadata(&id).await.is_err());
+    }
+
+    #[test]
+    fn delete_tokens() {
+        let metadata = PasteMetadata {
+            delete_token: Some("secret".to_owned()),
+            ..PasteMetadata::default()
+        };
+        assert!(metadata.is_delete_token("secret"));
+        assert!(!metadata.is_delete_token("secreT"));
+        assert!(!metadata.is_delete_token("secre"));
+        assert!(!metadata.is_delete_token(""));
+
+        // pastes saved without a token cannot be deleted
+        assert!(!PasteMetadata::default().is_delete_token(""));
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!(sanitize_extension("RS").as_deref(), Some("rs"));
+        assert_eq!(sanitize_extension("c++").as_deref(), Some("c++"));
+        assert_eq!(sanitize_extension("r\ns"), None);
+        assert_eq!(sanitize_extension("a b"), None);
+        assert_eq!(sanitize_extension(""), None);
+
+        let ext = |file_name| upload_extension(None, Some(file_name), FileKind::Text(None), b"");
+        assert_eq!(ext("a.RS").as_deref(), Some("rs"));
+        assert_eq!(ext("a.r\ns"), None);
+        assert_eq!(ext("a.r%s"), None);
+    }
 }
@@ -10,34 +10,55 @@ use serde::Deserialize;
 use tree_sitter_highlight::{Highlight, HighlightConfiguration, Highlighter, HtmlRenderer};
 
 macro_rules! impl_language {
-    ($(($lang:ident, $mod:ident, $($ext:expr),*),)+) => {
+    ($(($lang:ident, $mod:ident, $first:expr $(, $ext:expr)*),)+) => {
         #[derive(Clone, Copy, Debug)]
         pub enum Language {
             $($lang,)*
+            /// Unified diffs and patches, they have no grammar and are highlighted line by line.
+            Diff,
         }
 
         impl Language {
             pub fn as_str(&self) -> &'static str {
                 match self {
                     $(Self::$lang => stringify!($mod),)*
+                    Self::Diff => "diff",
                 }
             }
 
             pub fn from_extension(ext: &str) -> Option<Self> {
                 match ext {
-                    $($($ext => Some(Self::$lang),)*)+
+                    $($first $(| $ext)* => Some(Self::$lang),)+
+                    "diff" | "patch" => Some(Self::Diff),
                     _ => None
                 }
             }
 
-            fn config(&self) -> HighlightConfiguration {
+            /// The extension pastes of the language are shown with.
+            pub fn extension(&self) -> &'static str {
                 match self {
-                    $(Self::$lang => pepegsitter::$mod::highlight(),)*
+                    $(Self::$lang => $first,)*
+                    Self::Diff => "diff",
                 }
             }
 
-            fn all() -> &'static [Self] {
-                &[$(Self::$lang,)*]
+            /// The tree-sitter grammar of the language, `None` if it is highlighted without one.
+            pub(crate) fn grammar(&self) -> Option<tree_sitter::Language> {
+                match self {
+                    $(Self::$lang => Some(pepegsitter::$mod::language()),)*
+                    Self::Diff => None,
+                }
+            }
+
+            fn config(&self) -> Option<HighlightConfiguration> {
+                match self {
+                    $(Self::$lang => Some(pepegsitter::$mod::highlight()),)*
+                    Self::Diff => None,
+                }
+            }
+
+            pub(crate) fn all() -> &'static [Self] {
+                &[$(Self::$lang,)* Self::Diff]
             }
         }
     };
@@ -74,11 +95,11 @@ impl Theme {
     fn new(name: String, styles: Styles) -> Self {
         let configs = Language::all()
             .iter()
-            .map(|lang| {
-                let mut config = lang.config();
+            .filter_map(|lang| {
+                let mut config = lang.config()?;
                 config.configure(&styles.highlight_names);
 
-                (lang.as_str(), config)
+                Some((lang.as_str(), config))
             })
             .collect();
 
@@ -94,6 +115,10 @@ impl Theme {
     }
 
     pub fn highlight(&self, language: Language, source: &str) -> Option<Highlighted> {
+        if let Language::Diff = language {
+            return Some(Highlighted(self.highlight_diff(source)));
+        }
+
         let mut highlighter = Highlighter::new();
 
         let config = self.configs.get(language.as_str()).unwrap();
@@ -109,7 +134,106 @@ impl Theme {
             .render(&mut highlights, source.as_bytes(), &|h| self.styles.attr(h))
             .ok()?;
 
-        Some(Highlighted(renderer))
+        Some(Highlighted(
+            renderer.lines().map(ToOwned::to_owned).collect(),
+        ))
+    }
+
+    /// Colors added and removed lines as well as the headers of a unified diff, the lines
+    /// of each hunk are highlighted with the language of the file names in its headers.
+    fn highlight_diff(&self, source: &str) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut language = None;
+        let mut hunk = Hunk::default();
+
+        for line in source.split_inclusive('\n') {
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            if hunk.old_len > 0 || hunk.new_len > 0 {
+                hunk.push(line);
+                if hunk.old_len == 0 && hunk.new_len == 0 {
+                    lines.extend(self.highlight_hunk(language, &hunk.lines));
+                    hunk = Hunk::default();
+                }
+                continue;
+            }
+
+            let html = if let Some(hunk_header) = Hunk::parse(line) {
+                hunk = hunk_header;
+                self.span("diff.delta", &escape(line))
+            } else if line.starts_with("diff ") || line.starts_with("index ") {
+                self.span("diff.header", &escape(line))
+            } else if let Some(path) = line.strip_prefix("--- ").or(line.strip_prefix("+++ ")) {
+                // timestamps are separated by a tab, removed files are compared to /dev/null
+                let path = path.split('\t').next().unwrap_or_default();
+                if path != "/dev/null" {
+                    language = path
+                        .rsplit_once('.')
+                        .and_then(|(_, ext)| Language::from_extension(ext))
+                        .filter(|language| !matches!(language, Language::Diff));
+                }
+                self.span("diff.header", &escape(line))
+            } else if line.starts_with('\\') {
+                self.span("comment", &escape(line))
+            } else {
+                escape(line)
+            };
+            lines.push(html + "\n");
+        }
+
+        // a truncated diff ends in the middle of a hunk
+        lines.extend(self.highlight_hunk(language, &hunk.lines));
+        lines
+    }
+
+    /// Highlights the lines of a hunk, both the old and the new side of it are highlighted
+    /// on their own, each line is taken from the side it belongs to.
+    fn highlight_hunk(&self, language: Option<Language>, lines: &[&str]) -> Vec<String> {
+        let side = |removed: char| {
+            lines
+                .iter()
+                .filter(|line| !line.starts_with([removed, '\\']))
+                .map(|line| line.get(1..).unwrap_or_default())
+                .join("\n")
+        };
+        let highlight = |source: String| {
+            language
+                .and_then(|language| self.highlight(language, &source))
+                .map(|highlighted| highlighted.0)
+        };
+        let (old, new) = match (highlight(side('+')), highlight(side('-'))) {
+            (Some(old), Some(new)) => (old, new),
+            // without a language the whole line is colored
+            _ => (Vec::new(), Vec::new()),
+        };
+        let (mut old, mut new) = (old.into_iter(), new.into_iter());
+
+        let mut html = Vec::with_capacity(lines.len());
+        for line in lines {
+            let marker = line.get(..1).unwrap_or_default();
+            let (name, source) = match marker {
+                "-" => ("diff.minus", old.next()),
+                "+" => ("diff.plus", new.next()),
+                "\\" => ("comment", None),
+                _ => ("", old.next().and(new.next())),
+            };
+
+            let line = match source {
+                // only the marker is colored, the line keeps the colors of its language
+                Some(source) => self.span(name, &escape(marker)) + source.trim_end_matches('\n'),
+                None => self.span(name, &escape(line)),
+            };
+            html.push(line + "\n");
+        }
+        html
+    }
+
+    /// Wraps `html` in a span styled as the highlight `name`, if the theme has it.
+    fn span(&self, name: &str, html: &str) -> String {
+        match self.styles.attr_of(name) {
+            Some(attr) => format!("<span {attr}>{html}</span>"),
+            None => html.to_owned(),
+        }
     }
 
     pub fn css(&self) -> &str {
@@ -137,14 +261,71 @@ impl<'de> serde::de::Deserialize<'de> for Theme {
     }
 }
 
-pub struct Highlighted(HtmlRenderer);
+/// Highlighted HTML of each line, ending with the newline.
+pub struct Highlighted(Vec<String>);
 
 impl Highlighted {
     pub fn lines(&self) -> impl Iterator<Item = &str> {
-        self.0.lines()
+        self.0.iter().map(String::as_str)
     }
 }
 
+/// Lines of a hunk of a unified diff and how many lines of either side are still missing.
+#[derive(Default)]
+struct Hunk<'a> {
+    lines: Vec<&'a str>,
+    old_len: u64,
+    new_len: u64,
+}
+
+impl<'a> Hunk<'a> {
+    /// Parses a hunk header, e.g. `@@ -1,5 +1,6 @@ fn main() {`.
+    fn parse(line: &str) -> Option<Self> {
+        let ranges = line.strip_prefix("@@ -")?.split(" @@").next()?;
+        let (old, new) = ranges.split_once(" +")?;
+        // the length is omitted for hunks of a single line
+        let len = |range: &str| match range.split_once(',') {
+            Some((_, len)) => len.parse().ok(),
+            None => range.parse::<u64>().ok().map(|_| 1),
+        };
+
+        Some(Self {
+            lines: Vec::new(),
+            old_len: len(old)?,
+            new_len: len(new)?,
+        })
+    }
+
+    fn push(&mut self, line: &'a str) {
+        match line.as_bytes().first() {
+            Some(b'-') => self.old_len = self.old_len.saturating_sub(1),
+            Some(b'+') => self.new_len = self.new_len.saturating_sub(1),
+            Some(b'\\') => {}
+            // some editors strip the space of empty context lines
+            _ => {
+                self.old_len = self.old_len.saturating_sub(1);
+                self.new_len = self.new_len.saturating_sub(1);
+            }
+        }
+        self.lines.push(line);
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 #[derive(Debug)]
 struct Styles {
     highlight_names: Vec<String>,
@@ -181,6 +362,14 @@ impl Styles {
         self.attrs[h.0].as_bytes()
     }
 
+    fn attr_of(&self, highlight_name: &str) -> Option<&str> {
+        let index = self
+            .highlight_names
+            .iter()
+            .position(|name| name == highlight_name)?;
+        Some(&self.attrs[index])
+    }
+
     fn css_inner(&self) -> String {
         let mut css = String::new();
 
@@ -217,3 +406,111 @@ pub enum Style {
     Color(String),
     Attributes(BTreeMap<String, String>),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use once_cell::sync::Lazy;
+
+    fn theme() -> &'static Theme {
+        // compiling the highlight queries of all languages takes a while
+        static THEME: Lazy<Theme> =
+            Lazy::new(|| serde_json::from_str(include_str!("../themes/default.json")).unwrap());
+        &THEME
+    }
+
+    fn diff(theme: &Theme, source: &str) -> Vec<String> {
+        let lines = theme.highlight(Language::Diff, source).unwrap().0;
+        // every line is kept, even of broken diffs
+        assert_eq!(lines.len(), source.lines().count());
+        lines
+    }
+
+    #[test]
+    fn hunk_headers() {
+        let lens = |line| Hunk::parse(line).map(|hunk| (hunk.old_len, hunk.new_len));
+
+        assert_eq!(lens("@@ -1,5 +1,6 @@ fn main() {"), Some((5, 6)));
+        // single lines omit the length
+        assert_eq!(lens("@@ -1 +1 @@"), Some((1, 1)));
+        assert_eq!(lens("@@ -3 +2,0 @@"), Some((1, 0)));
+        assert_eq!(lens("@@ -0,0 +1,2 @@"), Some((0, 2)));
+        assert_eq!(lens("@@ -a +1 @@"), None);
+        assert_eq!(lens("@@ @@"), None);
+        assert_eq!(lens("--- a/main.rs"), None);
+    }
+
+    #[test]
+    fn highlight_single_line_hunk() {
+        let theme = theme();
+        let minus = theme.span("diff.minus", "-");
+        let plus = theme.span("diff.plus", "+");
+
+        let lines = diff(
+            theme,
+            "--- a/main.rs\n+++ b/main.rs\n@@ -1 +1 @@\n-fn a() {}\n+fn b() {}\ntrailing\n",
+        );
+        assert_eq!(lines[2], theme.span("diff.delta", "@@ -1 +1 @@") + "\n");
+        // the lines are highlighted as Rust, only the marker is colored as a change
+        assert!(lines[3].starts_with(&minus) && lines[3].contains("a"));
+        assert!(lines[4].starts_with(&plus) && lines[4].contains("b"));
+        assert_ne!(lines[3], theme.span("diff.minus", "-fn a() {}") + "\n");
+        // the hunk is complete, the line after it is not part of it
+        assert_eq!(lines[5], "trailing\n");
+    }
+
+    #[test]
+    fn highlight_removed_file() {
+        let theme = theme();
+
+        let lines = diff(
+            theme,
+            "--- a/main.rs\n+++ /dev/null\n@@ -1,2 +0,0 @@\n-fn main() {\n-}\n",
+        );
+        // highlighted with the language of the old file name
+        assert!(lines[3].starts_with(&theme.span("diff.minus", "-")));
+        assert_ne!(lines[3], theme.span("diff.minus", "-fn main() {") + "\n");
+    }
+
+    #[test]
+    fn highlight_no_newline() {
+        let theme = theme();
+
+        let lines = diff(
+            theme,
+            "--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-a\n\\ No newline at end of file\n+b\n\\ No newline at end of file\n@@ -5 +5 @@\n-c\n+d\n",
+        );
+        let no_newline = theme.span("comment", "\\ No newline at end of file") + "\n";
+        assert_eq!(lines[4], no_newline);
+        assert_eq!(lines[6], no_newline);
+        // without a language the whole line is colored
+        assert_eq!(lines[5], theme.span("diff.plus", "+b") + "\n");
+        assert_eq!(lines[7], theme.span("diff.delta", "@@ -5 +5 @@") + "\n");
+        assert_eq!(lines[9], theme.span("diff.plus", "+d") + "\n");
+    }
+
+    #[test]
+    fn highlight_truncated_hunk() {
+        let theme = theme();
+
+        let lines = diff(theme, "@@ -1,3 +1,4 @@\n a\n-b\n+c");
+        assert_eq!(lines[1], " a\n");
+        assert_eq!(lines[2], theme.span("diff.minus", "-b") + "\n");
+        assert_eq!(lines[3], theme.span("diff.plus", "+c") + "\n");
+
+        // a header without any lines
+        diff(theme, "--- a/a.rs\n+++ b/a.rs\n@@ -1,3 +1,4 @@\n");
+    }
+
+    #[test]
+    fn escape_html() {
+        let theme = theme();
+
+        let lines = diff(theme, "@@ -1 +1 @@\n-<a href='x'>\n+&amp;\n");
+        assert_eq!(
+            lines[1],
+            theme.span("diff.minus", "-&lt;a href=&#39;x&#39;&gt;") + "\n"
+        );
+        assert_eq!(lines[2], theme.span("diff.plus", "+&amp;amp;") + "\n");
+    }
+}
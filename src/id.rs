@@ -22,3 +22,8 @@ impl IdGen for RandomIdGen {
         )
     }
 }
+
+/// Generates a secret token, e.g. to authorize the deletion of a paste.
+pub fn generate_token() -> String {
+    Alphanumeric.sample_string(&mut rand::thread_rng(), 32)
+}
@@ -1,4 +1,9 @@
+pub mod api;
+mod archive;
+mod config;
+mod detect;
 mod error;
+pub mod expiry;
 pub mod handler;
 pub mod highlight;
 pub mod id;
@@ -6,13 +11,17 @@ pub mod storage;
 pub(crate) mod templates;
 mod utils;
 
+pub use self::config::{ByteSize, Config};
 pub use self::error::{Error, Result};
+pub use self::expiry::Expiry;
 pub use self::highlight::{Language, Theme};
 pub use self::id::{IdGen, RandomIdGen};
-pub use self::storage::{FilesystemStorage, PasteId, Storage};
+pub use self::storage::{
+    CompressedStorage, Encoding, EncryptedStorage, EncryptionKeys, FilesystemStorage,
+    MemoryStorage, PasteId, PasteMetadata, S3Storage, SqliteStorage, Storage,
+};
 pub use self::utils::WithExtension;
 
+pub type ConfigExtension = axum::Extension<std::sync::Arc<Config>>;
 pub type StorageExtension = axum::Extension<std::sync::Arc<dyn Storage + Send + Sync>>;
 pub type ThemeExtension = axum::Extension<std::sync::Arc<Theme>>;
-
-pub const MAX_FILE_SIZE: u64 = 10 * 1000 * 1024;
@@ -2,18 +2,258 @@ use axum::{
     routing::{get, post},
     Router,
 };
-use bpaf::Bpaf;
+use bpaf::{Bpaf, FromUtf8};
 
-use std::{net::SocketAddr, path::PathBuf};
+use farfalle::{ByteSize, Config, Encoding, EncryptionKeys, Expiry, StorageExtension};
+use std::{net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
+
+/// How often expired pastes are purged from the storage.
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+enum StorageKind {
+    Filesystem,
+    Memory,
+    S3,
+    Sqlite,
+}
+
+impl FromStr for StorageKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fs" | "filesystem" => Ok(Self::Filesystem),
+            "memory" => Ok(Self::Memory),
+            "s3" => Ok(Self::S3),
+            "sqlite" => Ok(Self::Sqlite),
+            _ => Err(format!(
+                "unknown storage '{s}', expected 'fs', 'memory', 's3' or 'sqlite'"
+            )),
+        }
+    }
+}
 
 #[derive(Debug, Clone, Bpaf)]
 #[bpaf(options)]
 struct Args {
+    /// Storage backend for pastes, `fs`, `memory`, `s3` or `sqlite`.
+    #[bpaf(
+        env("FARFALLE_STORAGE"),
+        argument::<FromUtf8<StorageKind>>("STORAGE"),
+        fallback(StorageKind::Filesystem)
+    )]
+    storage: StorageKind,
+
+    /// Directory of the `fs` storage or database file of the `sqlite` storage.
     #[bpaf(env("FARFALLE_PATH"))]
-    path: PathBuf,
+    path: Option<PathBuf>,
+
+    /// Spread pastes of the `fs` storage over subdirectories, e.g. `ab/cd/abcdXYZ`.
+    #[bpaf(env("FARFALLE_FS_SHARDED"))]
+    fs_sharded: bool,
+
+    /// Store identical pastes of the `fs` storage only once, not with encryption keys.
+    #[bpaf(env("FARFALLE_FS_DEDUP"))]
+    fs_dedup: bool,
+
+    /// Move pastes of the `fs` storage to the sharded layout and exit, safe while a server runs.
+    fs_migrate: bool,
+
+    /// Give pastes of the `fs` storage saved without metadata by older versions default
+    /// metadata and exit, run it while no server uses the storage.
+    fs_adopt_legacy: bool,
+
+    /// Maximum size of all pastes in bytes for the `memory` storage, unlimited by default.
+    #[bpaf(env("FARFALLE_MEMORY_CAPACITY"))]
+    memory_capacity: Option<u64>,
+
+    /// Endpoint of the `s3` storage, e.g. `http://localhost:9000`.
+    #[bpaf(env("FARFALLE_S3_ENDPOINT"))]
+    s3_endpoint: Option<String>,
+
+    #[bpaf(env("FARFALLE_S3_BUCKET"))]
+    s3_bucket: Option<String>,
+
+    #[bpaf(env("FARFALLE_S3_REGION"), fallback("us-east-1".to_owned()))]
+    s3_region: String,
+
+    /// Prefix of all object keys in the bucket.
+    #[bpaf(env("FARFALLE_S3_PREFIX"), fallback(String::new()))]
+    s3_prefix: String,
+
+    #[bpaf(env("AWS_ACCESS_KEY_ID"))]
+    s3_access_key: Option<String>,
+
+    #[bpaf(env("AWS_SECRET_ACCESS_KEY"))]
+    s3_secret_key: Option<String>,
 
     #[bpaf(env("FARFALLE_ADDR"), fallback(SocketAddr::from(([127, 0, 0, 1], 3000))))]
     addr: SocketAddr,
+
+    /// Keys to encrypt pastes at rest, `<id>:<64 hex digits>` separated by commas,
+    /// the key with the highest ID encrypts new pastes.
+    #[bpaf(
+        env("FARFALLE_ENCRYPTION_KEYS"),
+        argument::<FromUtf8<EncryptionKeys>>("KEYS"),
+        optional
+    )]
+    encryption_keys: Option<EncryptionKeys>,
+
+    /// File containing the encryption keys, in the same format as `--encryption-keys`.
+    #[bpaf(env("FARFALLE_ENCRYPTION_KEY_FILE"))]
+    encryption_key_file: Option<PathBuf>,
+
+    /// Compress new text pastes with `gzip` or `zstd`, stored as is by default.
+    #[bpaf(
+        env("FARFALLE_COMPRESSION"),
+        argument::<FromUtf8<Encoding>>("ENCODING"),
+        optional
+    )]
+    compression: Option<Encoding>,
+
+    /// Expiry of pastes which do not specify one, e.g. `1d` or `never`.
+    #[bpaf(
+        env("FARFALLE_DEFAULT_EXPIRY"),
+        argument::<FromUtf8<Expiry>>("EXPIRY"),
+        fallback(Expiry::Never)
+    )]
+    default_expiry: Expiry,
+
+    /// Maximum expiry a paste can request, e.g. `30d` or `never`.
+    #[bpaf(
+        env("FARFALLE_MAX_EXPIRY"),
+        argument::<FromUtf8<Expiry>>("EXPIRY"),
+        fallback(Expiry::Never)
+    )]
+    max_expiry: Expiry,
+
+    /// Maximum size of a text paste, e.g. `512KiB` or `10MB`.
+    #[bpaf(
+        env("FARFALLE_MAX_TEXT_SIZE"),
+        argument::<FromUtf8<ByteSize>>("SIZE"),
+        fallback(ByteSize(Config::DEFAULT_MAX_SIZE))
+    )]
+    max_text_size: ByteSize,
+
+    /// Maximum size of an image paste, e.g. `512KiB` or `10MB`.
+    #[bpaf(
+        env("FARFALLE_MAX_IMAGE_SIZE"),
+        argument::<FromUtf8<ByteSize>>("SIZE"),
+        fallback(ByteSize(Config::DEFAULT_MAX_SIZE))
+    )]
+    max_image_size: ByteSize,
+
+    /// Addresses of reverse proxies separated by commas, only their `Forwarded`,
+    /// `X-Forwarded-For` and `X-Forwarded-Proto` headers are trusted to name the client.
+    #[bpaf(env("FARFALLE_TRUSTED_PROXIES"))]
+    trusted_proxies: Option<String>,
+}
+
+async fn storage(args: &Args) -> Result<StorageExtension, Box<dyn std::error::Error>> {
+    let id_gen = farfalle::RandomIdGen::new(3);
+
+    let storage = match args.storage {
+        StorageKind::Filesystem => {
+            let path = args
+                .path
+                .clone()
+                .ok_or("--path is required for the fs storage")?;
+            let mut storage = if args.fs_sharded {
+                farfalle::FilesystemStorage::sharded(path, id_gen)
+            } else {
+                farfalle::FilesystemStorage::new(path, id_gen)
+            };
+            if args.fs_dedup {
+                // Pastes are encrypted with random nonces, identical pastes never match.
+                if args.encryption_keys.is_some() || args.encryption_key_file.is_some() {
+                    return Err("--fs-dedup cannot be combined with encryption keys".into());
+                }
+                storage = storage.deduplicated();
+            }
+
+            let removed = storage.cleanup().await?;
+            if removed > 0 {
+                tracing::info!("removed {removed} stale files from interrupted uploads");
+            }
+
+            storage.into_extension()
+        }
+        StorageKind::Memory => match args.memory_capacity {
+            Some(capacity) => farfalle::MemoryStorage::with_capacity(capacity, id_gen),
+            None => farfalle::MemoryStorage::new(id_gen),
+        }
+        .into_extension(),
+        StorageKind::S3 => {
+            let config = farfalle::storage::S3Config {
+                endpoint: args
+                    .s3_endpoint
+                    .clone()
+                    .ok_or("--s3-endpoint is required")?,
+                bucket: args.s3_bucket.clone().ok_or("--s3-bucket is required")?,
+                region: args.s3_region.clone(),
+                prefix: args.s3_prefix.clone(),
+                access_key: args
+                    .s3_access_key
+                    .clone()
+                    .ok_or("--s3-access-key is required")?,
+                secret_key: args
+                    .s3_secret_key
+                    .clone()
+                    .ok_or("--s3-secret-key is required")?,
+            };
+            farfalle::S3Storage::new(config, id_gen)?.into_extension()
+        }
+        StorageKind::Sqlite => {
+            let path = args
+                .path
+                .as_ref()
+                .ok_or("--path is required for the sqlite storage")?;
+            farfalle::SqliteStorage::new(path, id_gen)?.into_extension()
+        }
+    };
+
+    let keys = match (&args.encryption_keys, &args.encryption_key_file) {
+        (Some(keys), _) => Some(keys.clone()),
+        (None, Some(path)) => Some(std::fs::read_to_string(path)?.parse::<EncryptionKeys>()?),
+        (None, None) => None,
+    };
+    let storage = match keys {
+        Some(keys) => farfalle::EncryptedStorage::new(storage.0, keys).into_extension(),
+        None => storage,
+    };
+
+    // Compressed before encryption, encrypted data does not compress.
+    // Always wrapped, pastes compressed earlier are decompressed even with compression disabled.
+    let storage = farfalle::CompressedStorage::new(storage.0, args.compression).into_extension();
+
+    Ok(storage)
+}
+
+async fn migrate(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let path = args
+        .path
+        .clone()
+        .ok_or("--path is required for the migration")?;
+    let storage = farfalle::FilesystemStorage::sharded(path, farfalle::RandomIdGen::new(3));
+
+    let migrated = storage.migrate().await?;
+    tracing::info!("migrated {migrated} pastes to the sharded layout");
+
+    Ok(())
+}
+
+async fn adopt_legacy(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let path = args
+        .path
+        .clone()
+        .ok_or("--path is required to adopt legacy pastes")?;
+    let storage = farfalle::FilesystemStorage::new(path, farfalle::RandomIdGen::new(3));
+
+    let adopted = storage.adopt_legacy().await?;
+    tracing::info!("adopted {adopted} pastes without metadata");
+
+    Ok(())
 }
 
 #[tokio::main]
@@ -22,19 +262,68 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     tracing_subscriber::fmt::init();
 
-    let storage = farfalle::FilesystemStorage::new(args.path, farfalle::RandomIdGen::new(3));
+    if args.fs_migrate {
+        return migrate(&args).await;
+    }
+    if args.fs_adopt_legacy {
+        return adopt_legacy(&args).await;
+    }
+
+    let storage = storage(&args).await?;
     let theme: farfalle::Theme = serde_json::from_str(include_str!("../themes/default.json"))?;
+    let config = Config {
+        default_expiry: args.default_expiry,
+        max_expiry: args.max_expiry,
+        max_text_size: args.max_text_size.0,
+        max_image_size: args.max_image_size.0,
+        trusted_proxies: args
+            .trusted_proxies
+            .iter()
+            .flat_map(|proxies| proxies.split(','))
+            .map(|proxy| proxy.trim().parse())
+            .collect::<Result<_, _>>()?,
+    };
+
+    tokio::spawn(farfalle::storage::reap_expired(
+        storage.0.clone(),
+        REAP_INTERVAL,
+    ));
 
     let app = Router::new()
-        .route("/", get(farfalle::handler::root))
-        .route("/", post(farfalle::handler::upload))
-        .route("/:id", get(farfalle::handler::view))
-        .layer(storage.into_extension())
-        .layer(theme.into_extension());
+        .route(
+            "/",
+            get(farfalle::handler::root)
+                .post(farfalle::handler::upload)
+                .put(farfalle::handler::upload),
+        )
+        .route(
+            "/:id",
+            get(farfalle::handler::view)
+                .put(farfalle::handler::upload)
+                .delete(farfalle::handler::delete),
+        )
+        .route("/:id/raw", get(farfalle::handler::raw))
+        .route("/:id/rev/:rev", get(farfalle::handler::revision))
+        .route("/:id/history", get(farfalle::handler::history))
+        .route(
+            "/:id/edit",
+            get(farfalle::handler::edit_form)
+                .post(farfalle::handler::edit)
+                .put(farfalle::handler::edit),
+        )
+        .route("/:id/fork", get(farfalle::handler::fork))
+        .route("/diff/:a/:b", get(farfalle::handler::diff))
+        .route("/api/v1/pastes", post(farfalle::api::create_paste))
+        .route("/api/v1/pastes/:id", get(farfalle::api::get_paste))
+        .route("/:id/delete", get(farfalle::handler::delete))
+        .route("/:id/:file", get(farfalle::handler::file))
+        .layer(storage)
+        .layer(theme.into_extension())
+        .layer(config.into_extension());
 
     tracing::info!("listening on {}", args.addr);
     axum::Server::bind(&args.addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .with_graceful_shutdown(elegant_departure::tokio::depart().on_termination())
         .await?;
 
@@ -0,0 +1,208 @@
+use async_compression::tokio::bufread::{GzipDecoder, GzipEncoder, ZstdDecoder, ZstdEncoder};
+use axum::Extension;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, BufReader};
+
+use super::{
+    DeleteError, Encoding, LoadError, Paste, PasteData, PasteId, PasteMetadata, PurgeError,
+    SaveError, Storage,
+};
+use crate::StorageExtension;
+
+/// Compresses pastes before they are passed to another storage.
+///
+/// Only text is compressed, images are already compressed. Without an encoding
+/// new pastes are stored as is, previously compressed pastes are still decompressed.
+pub struct CompressedStorage {
+    inner: Arc<dyn Storage + Send + Sync>,
+    encoding: Option<Encoding>,
+}
+
+impl CompressedStorage {
+    pub fn new(inner: Arc<dyn Storage + Send + Sync>, encoding: Option<Encoding>) -> Self {
+        Self { inner, encoding }
+    }
+
+    pub fn into_extension(self) -> StorageExtension {
+        Extension(Arc::new(self))
+    }
+}
+
+fn compress<'a>(
+    data: Box<dyn AsyncRead + Send + Unpin + 'a>,
+    encoding: Encoding,
+) -> Box<dyn AsyncRead + Send + Unpin + 'a> {
+    let data = BufReader::new(data);
+    match encoding {
+        Encoding::Gzip => Box::new(GzipEncoder::new(data)),
+        Encoding::Zstd => Box::new(ZstdEncoder::new(data)),
+    }
+}
+
+fn decompress(
+    data: Box<dyn AsyncRead + Send + Sync + Unpin>,
+    encoding: Encoding,
+) -> Box<dyn AsyncRead + Send + Sync + Unpin> {
+    let data = BufReader::new(data);
+    match encoding {
+        Encoding::Gzip => Box::new(GzipDecoder::new(data)),
+        Encoding::Zstd => Box::new(ZstdDecoder::new(data)),
+    }
+}
+
+/// Decompresses a paste unless its encoding is `accepted`.
+pub(crate) fn decode(mut paste: Paste, accepted: &[Encoding]) -> Paste {
+    match paste.metadata.encoding {
+        Some(encoding) if !accepted.contains(&encoding) => {
+            paste.data = decompress(paste.data, encoding);
+            paste.metadata.encoding = None;
+            paste
+        }
+        _ => paste,
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for CompressedStorage {
+    async fn save_stream(
+        &self,
+        data: PasteData<'_>,
+        mut metadata: PasteMetadata,
+    ) -> Result<PasteId, SaveError> {
+        let is_text = matches!(&metadata.content_type, Some(ct) if ct.starts_with("text/"));
+
+        let data = match self.encoding {
+            Some(encoding) if is_text => {
+                metadata.encoding = Some(encoding);
+                data.map(|data| compress(data, encoding))
+            }
+            _ => data,
+        };
+
+        self.inner.save_stream(data, metadata).await
+    }
+
+    async fn load(&self, id: &PasteId) -> Result<Paste, LoadError> {
+        Ok(decode(self.inner.load(id).await?, &[]))
+    }
+
+    async fn load_encoded(&self, id: &PasteId, accepted: &[Encoding]) -> Result<Paste, LoadError> {
+        Ok(decode(self.inner.load(id).await?, accepted))
+    }
+
+    async fn metadata(&self, id: &PasteId) -> Result<PasteMetadata, LoadError> {
+        self.inner.metadata(id).await
+    }
+
+    async fn add_revision(&self, id: &PasteId, revision: &PasteId) -> Result<u32, SaveError> {
+        self.inner.add_revision(id, revision).await
+    }
+
+    async fn revisions(&self, id: &PasteId) -> Result<Vec<PasteId>, LoadError> {
+        self.inner.revisions(id).await
+    }
+
+    async fn delete(&self, id: &PasteId, token: &str) -> Result<(), DeleteError> {
+        self.inner.delete(id, token).await
+    }
+
+    async fn purge_expired(&self) -> Result<usize, PurgeError> {
+        self.inner.purge_expired().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryStorage, RandomIdGen};
+    use tokio::io::AsyncReadExt;
+
+    const TEXT: &str = "fn main() {\n    println!(\"hello world\");\n}\n";
+
+    fn text() -> PasteMetadata {
+        PasteMetadata {
+            content_type: Some("text/plain; charset=utf-8".to_owned()),
+            ..PasteMetadata::default()
+        }
+    }
+
+    async fn read(paste: Paste) -> (Vec<u8>, Option<Encoding>) {
+        let Paste { mut data, metadata } = paste;
+        let mut contents = Vec::new();
+        data.read_to_end(&mut contents).await.unwrap();
+        (contents, metadata.encoding)
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        for encoding in [Encoding::Gzip, Encoding::Zstd] {
+            let inner: Arc<dyn Storage + Send + Sync> =
+                Arc::new(MemoryStorage::new(RandomIdGen::new(8)));
+            let storage = CompressedStorage::new(Arc::clone(&inner), Some(encoding));
+
+            let id = storage.save(TEXT.into(), text()).await.unwrap();
+
+            let (stored, stored_encoding) = read(inner.load(&id).await.unwrap()).await;
+            assert_eq!(stored_encoding, Some(encoding));
+            assert_ne!(stored, TEXT.as_bytes());
+
+            let (data, encoding) = read(storage.load(&id).await.unwrap()).await;
+            assert_eq!(data, TEXT.as_bytes());
+            assert_eq!(encoding, None);
+
+            // size and digest are those of the original
+            let metadata = storage.metadata(&id).await.unwrap();
+            assert_eq!(metadata.size, TEXT.len() as u64);
+        }
+    }
+
+    #[tokio::test]
+    async fn uncompressed_pastes_pass_through() {
+        let inner: Arc<dyn Storage + Send + Sync> =
+            Arc::new(MemoryStorage::new(RandomIdGen::new(8)));
+        let storage = CompressedStorage::new(Arc::clone(&inner), Some(Encoding::Zstd));
+
+        // saved before compression was enabled
+        let old = inner.save(TEXT.into(), text()).await.unwrap();
+        let (data, encoding) =
+            read(storage.load_encoded(&old, &[Encoding::Zstd]).await.unwrap()).await;
+        assert_eq!(data, TEXT.as_bytes());
+        assert_eq!(encoding, None);
+
+        // images are already compressed
+        let image = PasteMetadata {
+            content_type: Some("image/png".to_owned()),
+            ..PasteMetadata::default()
+        };
+        let id = storage.save("png".into(), image).await.unwrap();
+        let (stored, encoding) = read(inner.load(&id).await.unwrap()).await;
+        assert_eq!(stored, b"png");
+        assert_eq!(encoding, None);
+
+        // compression disabled, compressed pastes are still decompressed
+        let id = storage.save(TEXT.into(), text()).await.unwrap();
+        let storage = CompressedStorage::new(Arc::clone(&inner), None);
+        let (data, _) = read(storage.load(&id).await.unwrap()).await;
+        assert_eq!(data, TEXT.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn compressed_only_if_accepted() {
+        let inner: Arc<dyn Storage + Send + Sync> =
+            Arc::new(MemoryStorage::new(RandomIdGen::new(8)));
+        let storage = CompressedStorage::new(Arc::clone(&inner), Some(Encoding::Gzip));
+        let id = storage.save(TEXT.into(), text()).await.unwrap();
+
+        let (data, encoding) =
+            read(storage.load_encoded(&id, &[Encoding::Gzip]).await.unwrap()).await;
+        assert_eq!(encoding, Some(Encoding::Gzip));
+        let (stored, _) = read(inner.load(&id).await.unwrap()).await;
+        assert_eq!(data, stored);
+
+        for accepted in [&[][..], &[Encoding::Zstd]] {
+            let (data, encoding) = read(storage.load_encoded(&id, accepted).await.unwrap()).await;
+            assert_eq!(data, TEXT.as_bytes());
+            assert_eq!(encoding, None);
+        }
+    }
+}
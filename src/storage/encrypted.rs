@@ -0,0 +1,446 @@
+use axum::Extension;
+use bytes::Bytes;
+use chacha20poly1305::{
+    aead::{
+        stream::{DecryptorBE32, EncryptorBE32},
+        Payload,
+    },
+    ChaCha20Poly1305, Key,
+};
+use rand::RngCore;
+use std::{collections::BTreeMap, fmt, io, mem, str::FromStr, sync::Arc};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::io::StreamReader;
+
+use super::{
+    DeleteError, Encoding, LoadError, Paste, PasteData, PasteId, PasteMetadata, PurgeError,
+    SaveError, Storage,
+};
+use crate::StorageExtension;
+
+const MAGIC: &[u8; 4] = b"FFE1";
+/// Nonce prefix of the STREAM construction, the remaining 5 bytes are its counter.
+const NONCE_LEN: usize = 7;
+const HEADER_LEN: usize = MAGIC.len() + 4 + NONCE_LEN;
+/// Plaintext size of a chunk, every chunk is authenticated on its own.
+const CHUNK_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+/// Keys for [`EncryptedStorage`], parsed from `<id>:<64 hex digits>` entries
+/// separated by commas or whitespace.
+///
+/// New pastes are encrypted with the key with the highest ID, older keys are kept to
+/// decrypt existing pastes.
+#[derive(Clone)]
+pub struct EncryptionKeys {
+    keys: BTreeMap<u32, Key>,
+}
+
+impl EncryptionKeys {
+    fn current(&self) -> (u32, &Key) {
+        // never empty, checked when parsing
+        let (id, key) = self.keys.iter().next_back().unwrap();
+        (*id, key)
+    }
+}
+
+impl FromStr for EncryptionKeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keys = BTreeMap::new();
+
+        for entry in s.split(|c: char| c == ',' || c.is_whitespace()) {
+            if entry.is_empty() {
+                continue;
+            }
+
+            let invalid = || "invalid encryption key, expected '<id>:<64 hex digits>'".to_owned();
+
+            let (id, hex) = entry.split_once(':').ok_or_else(invalid)?;
+            let id = id.parse::<u32>().map_err(|_| invalid())?;
+
+            let mut key = Key::default();
+            hex::decode_to_slice(hex, &mut key).map_err(|_| invalid())?;
+
+            if keys.insert(id, key).is_some() {
+                return Err(format!("duplicate encryption key id {id}"));
+            }
+        }
+
+        if keys.is_empty() {
+            return Err("no encryption keys".to_owned());
+        }
+
+        Ok(Self { keys })
+    }
+}
+
+impl fmt::Debug for EncryptionKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // never print the keys themselves
+        f.debug_struct("EncryptionKeys")
+            .field("ids", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Encrypts pastes with ChaCha20-Poly1305 before they are passed to another storage.
+///
+/// Contents are prefixed with a header of a magic, the key ID and a nonce, followed by
+/// the STREAM encrypted chunks, which are decrypted one at a time when read.
+pub struct EncryptedStorage {
+    inner: Arc<dyn Storage + Send + Sync>,
+    keys: EncryptionKeys,
+}
+
+impl EncryptedStorage {
+    pub fn new(inner: Arc<dyn Storage + Send + Sync>, keys: EncryptionKeys) -> Self {
+        Self { inner, keys }
+    }
+
+    pub fn into_extension(self) -> StorageExtension {
+        Extension(Arc::new(self))
+    }
+
+    /// Header for a new paste and the encryptor of its chunks.
+    fn encryptor(&self) -> (Vec<u8>, EncryptorBE32<ChaCha20Poly1305>) {
+        let (key_id, key) = self.keys.current();
+
+        let mut nonce = [0; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&key_id.to_be_bytes());
+        header.extend_from_slice(&nonce);
+
+        let encryptor = EncryptorBE32::<ChaCha20Poly1305>::new(key, &nonce.into());
+        (header, encryptor)
+    }
+
+    async fn decrypt(&self, mut paste: Paste) -> Result<Paste, LoadError> {
+        if !paste.metadata.encrypted {
+            return Ok(paste);
+        }
+
+        let mut header = vec![0; HEADER_LEN];
+        paste.data.read_exact(&mut header).await?;
+
+        let (magic, rest) = header.split_at(MAGIC.len());
+        let (key_id, nonce) = rest.split_at(4);
+        if magic != MAGIC {
+            return Err(invalid_data("invalid encryption header").into());
+        }
+
+        let key_id = u32::from_be_bytes(key_id.try_into().unwrap());
+        let key = self
+            .keys
+            .keys
+            .get(&key_id)
+            .ok_or_else(|| invalid_data(format!("unknown encryption key {key_id}")))?;
+
+        let decryptor = DecryptorBE32::<ChaCha20Poly1305>::new(key, nonce.into());
+        paste.data = Box::new(decrypt_stream(paste.data, decryptor, header));
+        paste.metadata.encrypted = false;
+
+        Ok(paste)
+    }
+}
+
+fn payload<'a>(msg: &'a [u8], aad: &'a [u8]) -> Payload<'a, 'a> {
+    Payload { msg, aad }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+struct EncryptState<'a> {
+    data: Box<dyn AsyncRead + Send + Unpin + 'a>,
+    encryptor: Option<EncryptorBE32<ChaCha20Poly1305>>,
+    header: Option<Vec<u8>>,
+    aad: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+/// Emits the header followed by the encrypted chunks, reading one chunk ahead to find the
+/// last one. The last chunk is marked as such, even if it is empty or full.
+fn encrypt_stream<'a>(
+    data: Box<dyn AsyncRead + Send + Unpin + 'a>,
+    encryptor: EncryptorBE32<ChaCha20Poly1305>,
+    header: Vec<u8>,
+) -> impl AsyncRead + Send + Unpin + 'a {
+    let state = EncryptState {
+        data,
+        encryptor: Some(encryptor),
+        header: Some(header.clone()),
+        aad: header,
+        buffer: Vec::with_capacity(CHUNK_LEN + 1),
+    };
+
+    let chunks = futures_util::stream::unfold(state, |mut state| async move {
+        if let Some(header) = state.header.take() {
+            return Some((Ok(Bytes::from(header)), state));
+        }
+
+        let mut encryptor = state.encryptor.take()?;
+
+        let missing = (CHUNK_LEN + 1).saturating_sub(state.buffer.len());
+        let read = (&mut state.data)
+            .take(missing as u64)
+            .read_to_end(&mut state.buffer)
+            .await;
+        if let Err(err) = read {
+            return Some((Err(err), state));
+        }
+
+        let encrypted = if state.buffer.len() > CHUNK_LEN {
+            let rest = state.buffer.split_off(CHUNK_LEN);
+            let chunk = mem::replace(&mut state.buffer, rest);
+
+            let encrypted = encryptor.encrypt_next(payload(&chunk, &state.aad));
+            state.encryptor = Some(encryptor);
+            encrypted
+        } else {
+            encryptor.encrypt_last(payload(&state.buffer, &state.aad))
+        };
+
+        let chunk = encrypted
+            .map(Bytes::from)
+            .map_err(|_| io::Error::other("failed to encrypt paste"));
+        Some((chunk, state))
+    });
+
+    StreamReader::new(Box::pin(chunks))
+}
+
+struct DecryptState {
+    data: Box<dyn AsyncRead + Send + Sync + Unpin>,
+    decryptor: Option<DecryptorBE32<ChaCha20Poly1305>>,
+    header: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+/// Decrypts the chunks following the header, reading one chunk ahead to find the last one.
+fn decrypt_stream(
+    data: Box<dyn AsyncRead + Send + Sync + Unpin>,
+    decryptor: DecryptorBE32<ChaCha20Poly1305>,
+    header: Vec<u8>,
+) -> impl AsyncRead + Send + Sync + Unpin {
+    const ENCRYPTED_CHUNK_LEN: usize = CHUNK_LEN + TAG_LEN;
+
+    let state = DecryptState {
+        data,
+        decryptor: Some(decryptor),
+        header,
+        buffer: Vec::with_capacity(ENCRYPTED_CHUNK_LEN + 1),
+    };
+
+    let chunks = futures_util::stream::unfold(state, |mut state| async move {
+        let mut decryptor = state.decryptor.take()?;
+
+        let missing = (ENCRYPTED_CHUNK_LEN + 1).saturating_sub(state.buffer.len());
+        let read = (&mut state.data)
+            .take(missing as u64)
+            .read_to_end(&mut state.buffer)
+            .await;
+        if let Err(err) = read {
+            return Some((Err(err), state));
+        }
+
+        let decrypted = if state.buffer.len() > ENCRYPTED_CHUNK_LEN {
+            let rest = state.buffer.split_off(ENCRYPTED_CHUNK_LEN);
+            let chunk = mem::replace(&mut state.buffer, rest);
+
+            let decrypted = decryptor.decrypt_next(payload(&chunk, &state.header));
+            state.decryptor = Some(decryptor);
+            decrypted
+        } else {
+            decryptor.decrypt_last(payload(&state.buffer, &state.header))
+        };
+
+        let chunk = decrypted
+            .map(Bytes::from)
+            .map_err(|_| invalid_data("failed to decrypt paste"));
+        Some((chunk, state))
+    });
+
+    StreamReader::new(Box::pin(chunks))
+}
+
+#[async_trait::async_trait]
+impl Storage for EncryptedStorage {
+    async fn save_stream(
+        &self,
+        data: PasteData<'_>,
+        mut metadata: PasteMetadata,
+    ) -> Result<PasteId, SaveError> {
+        let (header, encryptor) = self.encryptor();
+        metadata.encrypted = true;
+
+        let data = data.map(|data| encrypt_stream(data, encryptor, header));
+        self.inner.save_stream(data, metadata).await
+    }
+
+    async fn load(&self, id: &PasteId) -> Result<Paste, LoadError> {
+        self.decrypt(self.inner.load(id).await?).await
+    }
+
+    async fn load_encoded(&self, id: &PasteId, accepted: &[Encoding]) -> Result<Paste, LoadError> {
+        self.decrypt(self.inner.load_encoded(id, accepted).await?)
+            .await
+    }
+
+    async fn metadata(&self, id: &PasteId) -> Result<PasteMetadata, LoadError> {
+        self.inner.metadata(id).await
+    }
+
+    async fn add_revision(&self, id: &PasteId, revision: &PasteId) -> Result<u32, SaveError> {
+        self.inner.add_revision(id, revision).await
+    }
+
+    async fn revisions(&self, id: &PasteId) -> Result<Vec<PasteId>, LoadError> {
+        self.inner.revisions(id).await
+    }
+
+    async fn delete(&self, id: &PasteId, token: &str) -> Result<(), DeleteError> {
+        self.inner.delete(id, token).await
+    }
+
+    async fn purge_expired(&self) -> Result<usize, PurgeError> {
+        self.inner.purge_expired().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryStorage, RandomIdGen};
+    use std::io::Cursor;
+
+    const KEY_1: &str = "1:0101010101010101010101010101010101010101010101010101010101010101";
+    const KEY_2: &str = "2:0202020202020202020202020202020202020202020202020202020202020202";
+
+    fn storage(keys: &str) -> EncryptedStorage {
+        let inner = Arc::new(MemoryStorage::new(RandomIdGen::new(8)));
+        EncryptedStorage::new(inner, keys.parse().unwrap())
+    }
+
+    async fn encrypt(storage: &EncryptedStorage, data: &[u8]) -> Vec<u8> {
+        let (header, encryptor) = storage.encryptor();
+        let data = Box::new(Cursor::new(data.to_vec()));
+
+        let mut encrypted = Vec::new();
+        encrypt_stream(data, encryptor, header)
+            .read_to_end(&mut encrypted)
+            .await
+            .unwrap();
+        encrypted
+    }
+
+    async fn decrypt(storage: &EncryptedStorage, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        let paste = Paste {
+            data: Box::new(Cursor::new(data)),
+            metadata: PasteMetadata {
+                encrypted: true,
+                ..PasteMetadata::default()
+            },
+        };
+        let mut paste = storage.decrypt(paste).await.map_err(|e| e.to_string())?;
+
+        let mut decrypted = Vec::new();
+        paste
+            .data
+            .read_to_end(&mut decrypted)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(decrypted)
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let storage = storage(KEY_1);
+
+        for len in [
+            0,
+            1,
+            CHUNK_LEN - 1,
+            CHUNK_LEN,
+            CHUNK_LEN + 1,
+            3 * CHUNK_LEN + 7,
+        ] {
+            let data = (0..len).map(|i| i as u8).collect::<Vec<_>>();
+
+            let encrypted = encrypt(&storage, &data).await;
+            let chunks = len.div_ceil(CHUNK_LEN).max(1);
+            assert_eq!(encrypted.len(), HEADER_LEN + len + chunks * TAG_LEN);
+
+            assert_eq!(decrypt(&storage, encrypted).await.unwrap(), data, "{len}");
+        }
+    }
+
+    #[tokio::test]
+    async fn truncated() {
+        let storage = storage(KEY_1);
+        let data = vec![b'a'; 2 * CHUNK_LEN + 1];
+        let encrypted = encrypt(&storage, &data).await;
+
+        // only whole chunks left, the last one is missing
+        for len in [
+            HEADER_LEN + CHUNK_LEN + TAG_LEN,
+            encrypted.len() - 1,
+            HEADER_LEN,
+        ] {
+            let result = decrypt(&storage, encrypted[..len].to_vec()).await;
+            assert_eq!(result.unwrap_err(), "failed to decrypt paste", "{len}");
+        }
+
+        assert!(decrypt(&storage, encrypted[..HEADER_LEN - 1].to_vec())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn tampered() {
+        let storage = storage(KEY_1);
+        let mut encrypted = encrypt(&storage, b"hello world").await;
+
+        // the header is authenticated as well
+        encrypted[HEADER_LEN - 1] ^= 1;
+        assert_eq!(
+            decrypt(&storage, encrypted).await.unwrap_err(),
+            "failed to decrypt paste"
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_key() {
+        let encrypted = encrypt(&storage(KEY_2), b"hello world").await;
+
+        assert_eq!(
+            decrypt(&storage(KEY_1), encrypted).await.unwrap_err(),
+            "unknown encryption key 2"
+        );
+    }
+
+    #[tokio::test]
+    async fn rotated_keys() {
+        let old = encrypt(&storage(KEY_1), b"old").await;
+
+        let rotated = storage(&format!("{KEY_1},{KEY_2}"));
+        let new = encrypt(&rotated, b"new").await;
+        assert_eq!(new[MAGIC.len()..MAGIC.len() + 4], 2u32.to_be_bytes());
+
+        assert_eq!(decrypt(&rotated, old.clone()).await.unwrap(), b"old");
+        assert_eq!(decrypt(&rotated, new.clone()).await.unwrap(), b"new");
+
+        // once the old key is removed, only new pastes can be read
+        let rotated_out = storage(KEY_2);
+        assert_eq!(
+            decrypt(&rotated_out, old).await.unwrap_err(),
+            "unknown encryption key 1"
+        );
+        assert_eq!(decrypt(&rotated_out, new).await.unwrap(), b"new");
+    }
+}
@@ -0,0 +1,986 @@
+use axum::Extension;
+use rand::distributions::{Alphanumeric, DistString};
+use sha2::{Digest, Sha256};
+use std::{
+    ffi::OsStr,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::{
+    DeleteError, LoadError, Paste, PasteData, PasteId, PasteMetadata, PurgeError, SaveError,
+    Storage,
+};
+use crate::{utils::unix_now, IdGen, StorageExtension};
+
+/// Stores every paste as a file in `root`, next to a `<id>.meta` file with its metadata
+/// and a `<id>.revisions` file with the IDs of its revisions, one per line.
+///
+/// A paste ID is reserved by exclusively creating an empty file, the contents are
+/// written to a temporary file and renamed into place. The metadata is written last
+/// and is authoritative, a paste without metadata is an upload in progress and not visible.
+/// Pastes saved before metadata existed are only served after [`Self::adopt_legacy`].
+///
+/// With deduplication, identical contents are stored once in `.blobs/` and pastes are
+/// hard links to their blob, the link count of a blob is the amount of pastes using it.
+pub struct FilesystemStorage {
+    root: PathBuf,
+    sharded: bool,
+    deduplicated: bool,
+    id_gen: Box<dyn IdGen + Sync + Send>,
+}
+
+impl FilesystemStorage {
+    pub fn new(root: impl Into<PathBuf>, id_gen: impl IdGen + Send + Sync + 'static) -> Self {
+        Self {
+            root: root.into(),
+            sharded: false,
+            deduplicated: false,
+            id_gen: Box::new(id_gen),
+        }
+    }
+
+    /// Creates a storage which spreads pastes over subdirectories, e.g. `ab/cd/abcdXYZ`.
+    ///
+    /// Pastes which are still in the flat layout can be read, see [`Self::migrate`].
+    pub fn sharded(root: impl Into<PathBuf>, id_gen: impl IdGen + Send + Sync + 'static) -> Self {
+        Self {
+            sharded: true,
+            ..Self::new(root, id_gen)
+        }
+    }
+
+    /// Stores identical contents only once, requires hard links.
+    pub fn deduplicated(self) -> Self {
+        Self {
+            deduplicated: true,
+            ..self
+        }
+    }
+
+    pub fn into_extension(self) -> StorageExtension {
+        Extension(Arc::new(self))
+    }
+
+    fn metadata_path(path: &Path) -> PathBuf {
+        path.with_extension("meta")
+    }
+
+    fn revisions_path(path: &Path) -> PathBuf {
+        path.with_extension("revisions")
+    }
+
+    fn blobs_path(&self) -> PathBuf {
+        self.root.join(".blobs")
+    }
+
+    fn blob_path(&self, digest: &str) -> PathBuf {
+        self.blobs_path().join(&digest[..2]).join(digest)
+    }
+
+    fn flat_path(&self, id: &str) -> PathBuf {
+        self.root.join(id)
+    }
+
+    /// Path of a paste in the sharded layout, short IDs are padded with `_`.
+    fn shard_path(&self, id: &str) -> PathBuf {
+        let padded = format!("{id:_<4}");
+        self.root.join(&padded[..2]).join(&padded[2..4]).join(id)
+    }
+
+    /// Path new pastes are saved at.
+    fn path(&self, id: &str) -> PathBuf {
+        if self.sharded {
+            self.shard_path(id)
+        } else {
+            self.flat_path(id)
+        }
+    }
+
+    /// Path an existing paste is stored at, falls back to the flat layout for unmigrated pastes.
+    async fn locate(&self, id: &str) -> PathBuf {
+        let path = self.path(id);
+        if !self.sharded || tokio::fs::metadata(&path).await.is_ok() {
+            return path;
+        }
+        self.flat_path(id)
+    }
+
+    /// Lists all files in `root` and in shard directories.
+    async fn files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut dirs = vec![(self.root.clone(), 0)];
+
+        while let Some((dir, depth)) = dirs.pop() {
+            let mut entries = tokio::fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if !entry.file_type().await?.is_dir() {
+                    files.push(path);
+                    continue;
+                }
+
+                let name = entry.file_name();
+                let is_shard = name.len() == 2 && !name.to_string_lossy().starts_with('.');
+                if depth < 2 && is_shard {
+                    dirs.push((path, depth + 1));
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Reads the metadata of a paste, `None` if the paste does not exist (yet).
+    async fn read_metadata(path: &Path) -> io::Result<Option<PasteMetadata>> {
+        match tokio::fs::read(Self::metadata_path(path)).await {
+            Ok(data) => serde_json::from_slice(&data).map(Some).map_err(Into::into),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether `path` has contents but no metadata, like pastes saved before metadata existed.
+    ///
+    /// Uploads interrupted before their metadata was written look the same.
+    async fn lacks_metadata(path: &Path) -> io::Result<bool> {
+        if tokio::fs::metadata(Self::metadata_path(path)).await.is_ok() {
+            return Ok(false);
+        }
+        match tokio::fs::metadata(path).await {
+            Ok(m) => Ok(m.is_file() && m.len() > 0),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Unique path of a temporary file next to `path`.
+    fn temp_path(path: &Path) -> PathBuf {
+        path.with_file_name(format!(
+            ".{}.{}.tmp",
+            path.file_name().and_then(OsStr::to_str).unwrap_or_default(),
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 8)
+        ))
+    }
+
+    /// Atomically replaces `path` with `data`, readers either see the old or the new contents.
+    async fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+        let tmp = Self::temp_path(path);
+
+        let result = async {
+            let mut file = File::create(&tmp).await?;
+            file.write_all(data).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&tmp, path).await
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&tmp).await;
+        }
+        result
+    }
+
+    /// Streams `data` into a new temporary file next to `path`, returns it and the
+    /// hex encoded SHA-256 of the contents.
+    async fn write_temp(
+        path: &Path,
+        data: &mut PasteData<'_>,
+    ) -> Result<(PathBuf, String), SaveError> {
+        let tmp = Self::temp_path(path);
+
+        let result = async {
+            let mut file = File::create(&tmp).await?;
+            let mut hasher = Sha256::new();
+
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let read = data.read(&mut buf).await.map_err(SaveError::Read)?;
+                if read == 0 {
+                    break;
+                }
+
+                hasher.update(&buf[..read]);
+                file.write_all(&buf[..read]).await?;
+            }
+            file.sync_all().await?;
+
+            Ok(hex::encode(hasher.finalize()))
+        }
+        .await;
+
+        match result {
+            Ok(digest) => Ok((tmp, digest)),
+            Err(err) => {
+                let _ = tokio::fs::remove_file(&tmp).await;
+                Err(err)
+            }
+        }
+    }
+
+    /// Moves the contents in `tmp` to `path`, as a hard link to an existing blob with the same
+    /// contents or by making it the blob for later pastes.
+    async fn deduplicate(&self, digest: &str, tmp: &Path, path: &Path) -> io::Result<()> {
+        let blob = self.blob_path(digest);
+
+        let link = Self::temp_path(path);
+        match tokio::fs::hard_link(&blob, &link).await {
+            Ok(()) => {
+                tracing::debug!("linked existing blob {digest}");
+                tokio::fs::rename(&link, path).await?;
+                tokio::fs::remove_file(tmp).await
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                // The paste is in place before the blob exists,
+                // removing the last user of a blob can never lose the new contents.
+                tokio::fs::rename(tmp, path).await?;
+
+                if let Some(parent) = blob.parent() {
+                    Self::create_dirs(parent).await?;
+                }
+                let link = Self::temp_path(&blob);
+                tokio::fs::hard_link(path, &link).await?;
+                tokio::fs::rename(&link, &blob).await?;
+                Self::sync_parent(&blob).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Removes the blob of a removed paste, unless other pastes still use it.
+    ///
+    /// Pastes linking the blob concurrently keep their contents, only the blob is gone.
+    async fn release_blob(&self, metadata: &PasteMetadata) -> io::Result<()> {
+        let blob = match &metadata.digest {
+            Some(digest) => self.blob_path(digest),
+            None => return Ok(()),
+        };
+
+        match tokio::fs::metadata(&blob).await {
+            Ok(m) if link_count(&m) <= 1 => match tokio::fs::remove_file(&blob).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Makes the entries of `dir` durable, e.g. files renamed into it.
+    async fn sync_dir(dir: &Path) -> io::Result<()> {
+        File::open(dir).await?.sync_all().await
+    }
+
+    /// Makes the entry of `path` in its directory durable.
+    async fn sync_parent(path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => Self::sync_dir(parent).await,
+            _ => Self::sync_dir(Path::new(".")).await,
+        }
+    }
+
+    /// Creates `dir` and its missing parents, each created directory is made durable.
+    async fn create_dirs(dir: &Path) -> io::Result<()> {
+        let mut missing = Vec::new();
+        let mut current = dir;
+        while !current.as_os_str().is_empty() && tokio::fs::metadata(current).await.is_err() {
+            missing.push(current);
+            match current.parent() {
+                Some(parent) => current = parent,
+                None => break,
+            }
+        }
+
+        for dir in missing.into_iter().rev() {
+            match tokio::fs::create_dir(dir).await {
+                // created concurrently, it may not be durable yet either
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                result => result?,
+            }
+            Self::sync_parent(dir).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes leftovers of interrupted uploads and reads, should be run before the storage is used.
+    ///
+    /// Returns the amount of removed files.
+    pub async fn cleanup(&self) -> io::Result<usize> {
+        let mut count = 0;
+
+        for path in self.files().await? {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+            let stale = if name.starts_with('.') {
+                name.ends_with(".tmp") || name.ends_with(".burn")
+            } else if matches!(
+                path.extension().and_then(OsStr::to_str),
+                Some("meta" | "revisions")
+            ) {
+                // sidecars of pastes which were burned while reading
+                !path.with_extension("").exists()
+            } else if Self::lacks_metadata(&path).await? {
+                tracing::warn!(
+                    "{} has no metadata and is not served, see --fs-adopt-legacy",
+                    path.display()
+                );
+                false
+            } else {
+                // reservations which never received their contents
+                Self::read_metadata(&path).await?.is_none()
+            };
+
+            if stale {
+                tracing::info!("removing stale file {}", path.display());
+                tokio::fs::remove_file(&path).await?;
+                count += 1;
+            }
+        }
+
+        for blob in self.blobs().await? {
+            let name = blob.file_name().unwrap_or_default().to_string_lossy();
+
+            // blobs no paste links to anymore
+            let stale =
+                name.ends_with(".tmp") || link_count(&tokio::fs::metadata(&blob).await?) <= 1;
+
+            if stale {
+                tracing::info!("removing stale blob {}", blob.display());
+                tokio::fs::remove_file(&blob).await?;
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Lists all files in the blob directory.
+    async fn blobs(&self) -> io::Result<Vec<PathBuf>> {
+        let mut blobs = Vec::new();
+
+        let mut dirs = match tokio::fs::read_dir(self.blobs_path()).await {
+            Ok(dirs) => dirs,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(blobs),
+            Err(e) => return Err(e),
+        };
+        while let Some(dir) = dirs.next_entry().await? {
+            let mut entries = tokio::fs::read_dir(dir.path()).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                blobs.push(entry.path());
+            }
+        }
+
+        Ok(blobs)
+    }
+
+    /// Moves pastes from the flat to the sharded layout, can run while the storage is in use.
+    ///
+    /// Both files are linked into the shard before the flat copies are removed,
+    /// so readers always find a complete paste in one of the layouts.
+    /// Returns the amount of migrated pastes.
+    pub async fn migrate(&self) -> io::Result<usize> {
+        if !self.sharded {
+            return Ok(0);
+        }
+
+        let mut count = 0;
+
+        let mut entries = tokio::fs::read_dir(&self.root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let id = match path.file_name().and_then(OsStr::to_str) {
+                Some(id) if PasteId::new(id.to_owned()).is_ok() => id.to_owned(),
+                _ => continue,
+            };
+            let is_file = match entry.file_type().await {
+                Ok(file_type) => file_type.is_file(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+                Err(e) => return Err(e),
+            };
+            if !is_file || Self::read_metadata(&path).await?.is_none() {
+                continue;
+            }
+
+            let target = self.shard_path(&id);
+            if !self.migrate_paste(&path, &target).await? {
+                tracing::debug!("{id} was removed while migrating it");
+                continue;
+            }
+
+            tracing::debug!("migrated {id} to {}", target.display());
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Moves the paste at `path` to `target` in its shard, returns `false` if it was burned
+    /// or deleted meanwhile.
+    async fn migrate_paste(&self, path: &Path, target: &Path) -> io::Result<bool> {
+        if let Some(parent) = target.parent() {
+            Self::create_dirs(parent).await?;
+        }
+
+        // The sidecars go first, the paste only appears in the shard with its data file.
+        if !Self::link(&Self::metadata_path(path), &Self::metadata_path(target)).await? {
+            return Ok(false);
+        }
+        // pastes without revisions have none
+        Self::link(&Self::revisions_path(path), &Self::revisions_path(target)).await?;
+        if !Self::link(path, target).await? {
+            Self::remove(target).await?;
+            return Ok(false);
+        }
+
+        // the links have to be durable before the flat copies are gone
+        Self::sync_parent(target).await?;
+        Self::remove(path).await?;
+        Self::sync_dir(&self.root).await?;
+
+        Ok(true)
+    }
+
+    /// Hard links `from` to `to`, returns `false` if `from` does not exist.
+    async fn link(from: &Path, to: &Path) -> io::Result<bool> {
+        match tokio::fs::hard_link(from, to).await {
+            Ok(()) => Ok(true),
+            // an interrupted migration already linked it
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Gives pastes without metadata default metadata, so they are served again.
+    ///
+    /// Pastes saved before metadata existed have none, but neither do uploads which were
+    /// interrupted after their contents were in place. Run it once after upgrading and
+    /// while no server uses the storage, adopted uploads lose expiry and burn after reading.
+    /// Returns the amount of adopted pastes.
+    pub async fn adopt_legacy(&self) -> io::Result<usize> {
+        let mut count = 0;
+
+        for path in self.files().await? {
+            let is_paste = path
+                .file_name()
+                .and_then(OsStr::to_str)
+                .is_some_and(|id| PasteId::new(id.to_owned()).is_ok());
+            if !is_paste || !Self::lacks_metadata(&path).await? {
+                continue;
+            }
+
+            let metadata = PasteMetadata {
+                size: tokio::fs::metadata(&path).await?.len(),
+                ..PasteMetadata::default()
+            };
+            let encoded = serde_json::to_vec(&metadata).map_err(io::Error::from)?;
+            Self::write_atomic(&Self::metadata_path(&path), &encoded).await?;
+
+            tracing::debug!("adopted legacy paste at {}", path.display());
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    async fn remove(path: &Path) -> io::Result<()> {
+        for path in [
+            Self::metadata_path(path),
+            Self::revisions_path(path),
+            path.to_owned(),
+        ] {
+            match tokio::fs::remove_file(&path).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for FilesystemStorage {
+    #[tracing::instrument(err, skip(self, data, metadata))]
+    async fn save_stream(
+        &self,
+        mut data: PasteData<'_>,
+        mut metadata: PasteMetadata,
+    ) -> Result<PasteId, SaveError> {
+        let mut reserved = None;
+        for attempt in 0..10 {
+            let id = self.id_gen.next_id(attempt);
+
+            let path = self.path(&id);
+            let result = async {
+                if self.sharded {
+                    // the ID may still be taken by an unmigrated paste
+                    if tokio::fs::metadata(self.flat_path(&id)).await.is_ok() {
+                        return Err(io::ErrorKind::AlreadyExists.into());
+                    }
+                    if let Some(parent) = path.parent() {
+                        Self::create_dirs(parent).await?;
+                    }
+                }
+
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)
+                    .await
+            }
+            .await;
+
+            match result {
+                Ok(_) => {
+                    reserved = Some((id, path));
+                    break;
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    tracing::error!("failed to reserve paste at {}: {e}", path.display());
+                    return Err(e.into());
+                }
+            }
+        }
+
+        let (id, path) = reserved.ok_or(SaveError::Failed)?;
+
+        tracing::debug!("saving {id} at {}", path.display());
+
+        let result = async {
+            let (tmp, digest) = Self::write_temp(&path, &mut data).await?;
+            data.finish(&mut metadata);
+
+            if self.deduplicated {
+                self.deduplicate(&digest, &tmp, &path).await?;
+                metadata.digest = Some(digest);
+            } else {
+                tokio::fs::rename(&tmp, &path).await?;
+            }
+
+            let encoded = serde_json::to_vec(&metadata).map_err(io::Error::from)?;
+            Self::write_atomic(&Self::metadata_path(&path), &encoded).await?;
+            Self::sync_parent(&path).await?;
+
+            Ok::<_, SaveError>(())
+        }
+        .await;
+
+        if let Err(err) = result {
+            tracing::error!("failed to save {id} at {}: {err}", path.display());
+            let _ = Self::remove(&path).await;
+            let _ = self.release_blob(&metadata).await;
+            return Err(err);
+        }
+
+        tracing::info!("saved {id} at {}", path.display());
+
+        Ok(PasteId(id))
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn load(&self, id: &PasteId) -> Result<Paste, LoadError> {
+        let path = self.locate(id).await;
+
+        tracing::debug!("trying to load paste {id} from {}", path.display());
+
+        let metadata = Self::read_metadata(&path)
+            .await?
+            .ok_or(LoadError::NotFound)?;
+        if metadata.is_expired(unix_now()) {
+            return Err(LoadError::Expired);
+        }
+
+        let file = if metadata.burn_after_reading {
+            // Only one reader can win the rename, everyone else sees the paste as gone.
+            // The open file stays readable after it has been unlinked.
+            let burned = self.root.join(format!(".{id}.burn"));
+            tokio::fs::rename(&path, &burned)
+                .await
+                .map_err(|e| match e.kind() {
+                    io::ErrorKind::NotFound => LoadError::NotFound,
+                    _ => LoadError::IoError(e),
+                })?;
+
+            let file = File::open(&burned).await?;
+            tokio::fs::remove_file(&burned).await?;
+            Self::remove(&path).await?;
+            self.release_blob(&metadata).await?;
+            // a burned paste must not come back after a crash
+            Self::sync_parent(&path).await?;
+
+            tracing::info!("burned paste {id} from {}", path.display());
+
+            file
+        } else {
+            let file = File::open(&path).await.map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => LoadError::NotFound,
+                _ => LoadError::IoError(e),
+            })?;
+
+            tracing::info!("loaded paste {id} from {}", path.display());
+
+            file
+        };
+
+        Ok(Paste {
+            data: Box::new(file),
+            metadata,
+        })
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn metadata(&self, id: &PasteId) -> Result<PasteMetadata, LoadError> {
+        let path = self.locate(id).await;
+
+        let metadata = Self::read_metadata(&path)
+            .await?
+            .ok_or(LoadError::NotFound)?;
+        if metadata.is_expired(unix_now()) {
+            return Err(LoadError::Expired);
+        }
+
+        Ok(metadata)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn add_revision(&self, id: &PasteId, revision: &PasteId) -> Result<u32, SaveError> {
+        let path = Self::revisions_path(&self.locate(id).await);
+
+        // Appending a single line is atomic, concurrent revisions get distinct numbers.
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .await?;
+        file.write_all(format!("{revision}\n").as_bytes()).await?;
+        file.sync_all().await?;
+
+        let revisions = self.revisions(id).await.map_err(|_| SaveError::Failed)?;
+        let index = revisions
+            .iter()
+            .position(|id| id.as_str() == revision.as_str())
+            .ok_or(SaveError::Failed)?;
+
+        Ok(index as u32 + 2)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn revisions(&self, id: &PasteId) -> Result<Vec<PasteId>, LoadError> {
+        let path = Self::revisions_path(&self.locate(id).await);
+
+        let revisions = match tokio::fs::read_to_string(&path).await {
+            Ok(revisions) => revisions,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let revisions = revisions
+            .lines()
+            .filter_map(|id| PasteId::new(id.to_owned()).ok())
+            .collect();
+        Ok(revisions)
+    }
+
+    #[tracing::instrument(err, skip(self, token))]
+    async fn delete(&self, id: &PasteId, token: &str) -> Result<(), DeleteError> {
+        let path = self.locate(id).await;
+
+        let metadata = Self::read_metadata(&path)
+            .await?
+            .ok_or(DeleteError::NotFound)?;
+        if !metadata.is_delete_token(token) {
+            return Err(DeleteError::InvalidToken);
+        }
+
+        Self::remove(&path).await?;
+        self.release_blob(&metadata).await?;
+
+        tracing::info!("deleted paste {id} from {}", path.display());
+
+        Ok(())
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn purge_expired(&self) -> Result<usize, PurgeError> {
+        let now = unix_now();
+        let mut count = 0;
+
+        for path in self.files().await? {
+            if path.extension() != Some(OsStr::new("meta")) {
+                continue;
+            }
+
+            let path = path.with_extension("");
+            let metadata = match Self::read_metadata(&path).await {
+                Ok(Some(metadata)) if metadata.is_expired(now) => metadata,
+                Ok(_) => continue,
+                Err(err) => {
+                    tracing::warn!("failed to read metadata of {}: {err}", path.display());
+                    continue;
+                }
+            };
+
+            tracing::debug!("removing expired paste at {}", path.display());
+            Self::remove(&path).await?;
+            self.release_blob(&metadata).await?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+#[cfg(unix)]
+fn link_count(metadata: &std::fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::nlink(metadata)
+}
+
+/// Without link counts blobs are never considered unused.
+#[cfg(not(unix))]
+fn link_count(_: &std::fs::Metadata) -> u64 {
+    u64::MAX
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RandomIdGen;
+
+    fn storage() -> FilesystemStorage {
+        let root = std::env::temp_dir().join(format!(
+            "farfalle-test-{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 8)
+        ));
+        std::fs::create_dir_all(&root).unwrap();
+        FilesystemStorage::new(root, RandomIdGen::new(8))
+    }
+
+    #[tokio::test]
+    async fn save_sets_size_and_digest() {
+        let storage = storage();
+
+        let id = storage
+            .save("hello world".into(), PasteMetadata::default())
+            .await
+            .unwrap();
+
+        let metadata = storage.metadata(&id).await.unwrap();
+        assert_eq!(metadata.size, 11);
+        assert_eq!(
+            metadata.content_digest.as_deref(),
+            Some("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9")
+        );
+
+        tokio::fs::remove_dir_all(&storage.root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pastes_without_metadata_are_not_served() {
+        let storage = storage();
+
+        let metadata = PasteMetadata {
+            burn_after_reading: true,
+            ..PasteMetadata::default()
+        };
+        let id = storage.save("secret".into(), metadata).await.unwrap();
+
+        // an upload interrupted after its contents were renamed into place
+        let path = storage.path(&id);
+        tokio::fs::remove_file(FilesystemStorage::metadata_path(&path))
+            .await
+            .unwrap();
+
+        assert!(matches!(storage.load(&id).await, Err(LoadError::NotFound)));
+        assert!(matches!(
+            storage.metadata(&id).await,
+            Err(LoadError::NotFound)
+        ));
+        assert_eq!(storage.cleanup().await.unwrap(), 0);
+        assert!(path.exists());
+
+        tokio::fs::remove_dir_all(&storage.root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn adopt_legacy_pastes() {
+        let storage = storage();
+
+        tokio::fs::write(storage.flat_path("legacy"), "old")
+            .await
+            .unwrap();
+        // a reservation, not a paste
+        tokio::fs::write(storage.flat_path("reserved"), "")
+            .await
+            .unwrap();
+
+        let legacy = PasteId::new("legacy".to_owned()).unwrap();
+        assert!(matches!(
+            storage.metadata(&legacy).await,
+            Err(LoadError::NotFound)
+        ));
+
+        assert_eq!(storage.adopt_legacy().await.unwrap(), 1);
+        assert_eq!(storage.adopt_legacy().await.unwrap(), 0);
+
+        let mut paste = storage.load(&legacy).await.unwrap();
+        let mut data = String::new();
+        paste.data.read_to_string(&mut data).await.unwrap();
+        assert_eq!(data, "old");
+        assert_eq!(paste.metadata.size, 3);
+        assert!(!paste.metadata.burn_after_reading);
+
+        assert_eq!(storage.cleanup().await.unwrap(), 1);
+        assert!(!storage.flat_path("reserved").exists());
+
+        tokio::fs::remove_dir_all(&storage.root).await.unwrap();
+    }
+
+    async fn read(storage: &FilesystemStorage, id: &PasteId) -> String {
+        let mut paste = storage.load(id).await.unwrap();
+        let mut data = String::new();
+        paste.data.read_to_string(&mut data).await.unwrap();
+        data
+    }
+
+    fn deletable() -> PasteMetadata {
+        PasteMetadata {
+            delete_token: Some("token".to_owned()),
+            ..PasteMetadata::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn identical_pastes_share_a_blob() {
+        let storage = storage().deduplicated();
+
+        let a = storage.save("same".into(), deletable()).await.unwrap();
+        let b = storage.save("same".into(), deletable()).await.unwrap();
+        storage.save("other".into(), deletable()).await.unwrap();
+
+        let blobs = storage.blobs().await.unwrap();
+        assert_eq!(blobs.len(), 2);
+
+        let blob = storage.blob_path(&storage.metadata(&a).await.unwrap().digest.unwrap());
+        let links = || link_count(&std::fs::metadata(&blob).unwrap());
+        assert_eq!(links(), 3);
+
+        // the other paste keeps the blob
+        storage.delete(&a, "token").await.unwrap();
+        assert_eq!(links(), 2);
+        assert_eq!(read(&storage, &b).await, "same");
+
+        storage.delete(&b, "token").await.unwrap();
+        assert!(!blob.exists());
+        assert_eq!(storage.blobs().await.unwrap().len(), 1);
+
+        tokio::fs::remove_dir_all(&storage.root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn burning_releases_the_blob() {
+        let storage = storage().deduplicated();
+
+        let metadata = PasteMetadata {
+            burn_after_reading: true,
+            ..PasteMetadata::default()
+        };
+        let id = storage.save("secret".into(), metadata).await.unwrap();
+        assert_eq!(storage.blobs().await.unwrap().len(), 1);
+
+        assert_eq!(read(&storage, &id).await, "secret");
+        assert!(storage.blobs().await.unwrap().is_empty());
+
+        tokio::fs::remove_dir_all(&storage.root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cleanup_removes_unused_blobs() {
+        let storage = storage().deduplicated();
+
+        let id = storage.save("used".into(), deletable()).await.unwrap();
+
+        // left behind by a crash between removing a paste and its blob
+        let digest = hex::encode(Sha256::digest(b"unused"));
+        let unused = storage.blob_path(&digest);
+        tokio::fs::create_dir_all(unused.parent().unwrap())
+            .await
+            .unwrap();
+        tokio::fs::write(&unused, "unused").await.unwrap();
+
+        assert_eq!(storage.cleanup().await.unwrap(), 1);
+        assert!(!unused.exists());
+        assert_eq!(storage.blobs().await.unwrap().len(), 1);
+        assert_eq!(read(&storage, &id).await, "used");
+
+        tokio::fs::remove_dir_all(&storage.root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn migrate_to_shards() {
+        let flat = storage();
+        let sharded = FilesystemStorage::sharded(flat.root.clone(), RandomIdGen::new(8));
+
+        let a = flat
+            .save("a".into(), PasteMetadata::default())
+            .await
+            .unwrap();
+        let b = flat
+            .save("b".into(), PasteMetadata::default())
+            .await
+            .unwrap();
+        let revision = flat
+            .save("b2".into(), PasteMetadata::default())
+            .await
+            .unwrap();
+        flat.add_revision(&b, &revision).await.unwrap();
+
+        // readable before and after
+        assert_eq!(read(&sharded, &a).await, "a");
+        assert_eq!(sharded.migrate().await.unwrap(), 3);
+        assert_eq!(sharded.migrate().await.unwrap(), 0);
+
+        for id in [&a, &b, &revision] {
+            assert!(!flat.flat_path(id).exists());
+            assert!(!FilesystemStorage::metadata_path(&flat.flat_path(id)).exists());
+            assert!(sharded.shard_path(id).exists());
+        }
+        assert_eq!(read(&sharded, &a).await, "a");
+        let revisions = sharded.revisions(&b).await.unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].as_str(), revision.as_str());
+
+        tokio::fs::remove_dir_all(&flat.root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn migrate_skips_removed_pastes() {
+        let flat = storage();
+        let sharded = FilesystemStorage::sharded(flat.root.clone(), RandomIdGen::new(8));
+
+        let id = flat
+            .save("a".into(), PasteMetadata::default())
+            .await
+            .unwrap();
+        let (path, target) = (flat.flat_path(&id), sharded.shard_path(&id));
+
+        // burned after it was listed, its data is gone before its metadata
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert!(!sharded.migrate_paste(&path, &target).await.unwrap());
+        assert!(!FilesystemStorage::metadata_path(&target).exists());
+
+        // deleted after it was listed
+        tokio::fs::remove_file(FilesystemStorage::metadata_path(&path))
+            .await
+            .unwrap();
+        assert!(!sharded.migrate_paste(&path, &target).await.unwrap());
+        assert!(!target.exists());
+
+        assert_eq!(sharded.migrate().await.unwrap(), 0);
+
+        tokio::fs::remove_dir_all(&flat.root).await.unwrap();
+    }
+}
@@ -0,0 +1,330 @@
+use axum::Extension;
+use bytes::Bytes;
+use lru::LruCache;
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use super::{
+    DeleteError, LoadError, Paste, PasteData, PasteId, PasteMetadata, PurgeError, SaveError,
+    Storage,
+};
+use crate::{utils::unix_now, IdGen, StorageExtension};
+
+struct Entry {
+    data: Bytes,
+    metadata: PasteMetadata,
+}
+
+struct Inner {
+    pastes: LruCache<String, Entry>,
+    /// Total size of all stored pastes in bytes.
+    size: u64,
+    /// IDs of the revisions of a paste, oldest first.
+    revisions: HashMap<String, Vec<String>>,
+}
+
+impl Inner {
+    fn remove(&mut self, id: &str) -> Option<Entry> {
+        let entry = self.pastes.pop(id)?;
+        self.size -= entry.data.len() as u64;
+        self.revisions.remove(id);
+        Some(entry)
+    }
+}
+
+/// Keeps pastes in memory, useful for tests and ephemeral instances.
+///
+/// With a capacity, the least recently used pastes are evicted to make room for new ones.
+pub struct MemoryStorage {
+    inner: Mutex<Inner>,
+    capacity: Option<u64>,
+    id_gen: Box<dyn IdGen + Sync + Send>,
+}
+
+impl MemoryStorage {
+    pub fn new(id_gen: impl IdGen + Send + Sync + 'static) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                pastes: LruCache::unbounded(),
+                size: 0,
+                revisions: HashMap::new(),
+            }),
+            capacity: None,
+            id_gen: Box::new(id_gen),
+        }
+    }
+
+    /// Creates a storage which holds at most `capacity` bytes of pastes.
+    pub fn with_capacity(capacity: u64, id_gen: impl IdGen + Send + Sync + 'static) -> Self {
+        Self {
+            capacity: Some(capacity),
+            ..Self::new(id_gen)
+        }
+    }
+
+    pub fn into_extension(self) -> StorageExtension {
+        Extension(Arc::new(self))
+    }
+
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        // the inner state is always consistent, even after a panic
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for MemoryStorage {
+    #[tracing::instrument(err, skip(self, paste, metadata))]
+    async fn save_stream(
+        &self,
+        mut paste: PasteData<'_>,
+        mut metadata: PasteMetadata,
+    ) -> Result<PasteId, SaveError> {
+        let data = paste.read_to_bytes().await?;
+        paste.finish(&mut metadata);
+
+        let size = data.len() as u64;
+        if matches!(self.capacity, Some(capacity) if size > capacity) {
+            tracing::warn!("paste of {size} bytes exceeds the capacity");
+            return Err(SaveError::Failed);
+        }
+
+        let mut inner = self.inner();
+
+        let id = (0..10)
+            .map(|attempt| self.id_gen.next_id(attempt))
+            .find(|id| !inner.pastes.contains(id))
+            .ok_or(SaveError::Failed)?;
+
+        if let Some(capacity) = self.capacity {
+            while inner.size + size > capacity {
+                let (evicted, entry) = match inner.pastes.pop_lru() {
+                    Some(evicted) => evicted,
+                    None => break,
+                };
+                inner.size -= entry.data.len() as u64;
+                inner.revisions.remove(&evicted);
+
+                tracing::debug!("evicted paste {evicted}");
+            }
+        }
+
+        inner.size += size;
+        inner.pastes.put(id.clone(), Entry { data, metadata });
+
+        tracing::info!("saved {id}");
+
+        Ok(PasteId(id))
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn load(&self, id: &PasteId) -> Result<Paste, LoadError> {
+        let mut inner = self.inner();
+
+        let entry = inner.pastes.get(id.as_str()).ok_or(LoadError::NotFound)?;
+        if entry.metadata.is_expired(unix_now()) {
+            return Err(LoadError::Expired);
+        }
+
+        let (data, metadata) = if entry.metadata.burn_after_reading {
+            // removed while holding the lock, no other reader can get it
+            let entry = inner.remove(id).ok_or(LoadError::NotFound)?;
+            (entry.data, entry.metadata)
+        } else {
+            (entry.data.clone(), entry.metadata.clone())
+        };
+
+        tracing::info!("loaded paste {id}");
+
+        Ok(Paste {
+            data: Box::new(Cursor::new(data)),
+            metadata,
+        })
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn metadata(&self, id: &PasteId) -> Result<PasteMetadata, LoadError> {
+        let inner = self.inner();
+
+        let entry = inner.pastes.peek(id.as_str()).ok_or(LoadError::NotFound)?;
+        if entry.metadata.is_expired(unix_now()) {
+            return Err(LoadError::Expired);
+        }
+
+        Ok(entry.metadata.clone())
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn add_revision(&self, id: &PasteId, revision: &PasteId) -> Result<u32, SaveError> {
+        let mut inner = self.inner();
+
+        let revisions = inner.revisions.entry(id.to_string()).or_default();
+        revisions.push(revision.to_string());
+
+        Ok(revisions.len() as u32 + 1)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn revisions(&self, id: &PasteId) -> Result<Vec<PasteId>, LoadError> {
+        let inner = self.inner();
+
+        let revisions = inner.revisions.get(id.as_str()).into_iter().flatten();
+        Ok(revisions.map(|id| PasteId(id.clone())).collect())
+    }
+
+    #[tracing::instrument(err, skip(self, token))]
+    async fn delete(&self, id: &PasteId, token: &str) -> Result<(), DeleteError> {
+        let mut inner = self.inner();
+
+        let entry = inner
+            .pastes
+            .peek(id.as_str())
+            .ok_or(DeleteError::NotFound)?;
+        if !entry.metadata.is_delete_token(token) {
+            return Err(DeleteError::InvalidToken);
+        }
+
+        inner.remove(id);
+
+        tracing::info!("deleted paste {id}");
+
+        Ok(())
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn purge_expired(&self) -> Result<usize, PurgeError> {
+        let now = unix_now();
+        let mut inner = self.inner();
+
+        let expired = inner
+            .pastes
+            .iter()
+            .filter(|(_, entry)| entry.metadata.is_expired(now))
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+
+        for id in &expired {
+            inner.remove(id);
+        }
+
+        Ok(expired.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RandomIdGen;
+
+    #[tokio::test]
+    async fn save_sets_size_and_digest() {
+        let storage = MemoryStorage::new(RandomIdGen::new(8));
+
+        let id = storage
+            .save("hello world".into(), PasteMetadata::default())
+            .await
+            .unwrap();
+
+        let metadata = storage.metadata(&id).await.unwrap();
+        assert_eq!(metadata.size, 11);
+        assert_eq!(
+            metadata.content_digest.as_deref(),
+            Some("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9")
+        );
+    }
+
+    fn size(storage: &MemoryStorage) -> u64 {
+        storage.inner().size
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used() {
+        let storage = MemoryStorage::with_capacity(10, RandomIdGen::new(8));
+
+        let a = storage
+            .save("aaaa".into(), PasteMetadata::default())
+            .await
+            .unwrap();
+        let b = storage
+            .save("bbbb".into(), PasteMetadata::default())
+            .await
+            .unwrap();
+        assert_eq!(size(&storage), 8);
+
+        // loading marks it as used, the metadata alone does not
+        storage.load(&a).await.unwrap();
+        storage.metadata(&b).await.unwrap();
+
+        let c = storage
+            .save("cccc".into(), PasteMetadata::default())
+            .await
+            .unwrap();
+        assert_eq!(size(&storage), 8);
+        assert!(storage.load(&a).await.is_ok());
+        assert!(matches!(storage.load(&b).await, Err(LoadError::NotFound)));
+        assert!(storage.load(&c).await.is_ok());
+
+        // as many as needed are evicted
+        let d = storage
+            .save("dddddddddd".into(), PasteMetadata::default())
+            .await
+            .unwrap();
+        assert_eq!(size(&storage), 10);
+        assert!(matches!(storage.load(&a).await, Err(LoadError::NotFound)));
+        assert!(matches!(storage.load(&c).await, Err(LoadError::NotFound)));
+        assert!(storage.load(&d).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_pastes_larger_than_the_capacity() {
+        let storage = MemoryStorage::with_capacity(10, RandomIdGen::new(8));
+
+        let a = storage
+            .save("aaaa".into(), PasteMetadata::default())
+            .await
+            .unwrap();
+        assert!(matches!(
+            storage
+                .save("too large!!".into(), PasteMetadata::default())
+                .await,
+            Err(SaveError::Failed)
+        ));
+
+        // nothing was evicted for it
+        assert_eq!(size(&storage), 4);
+        assert!(storage.load(&a).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn removing_frees_space() {
+        let storage = MemoryStorage::with_capacity(10, RandomIdGen::new(8));
+
+        let deletable = PasteMetadata {
+            delete_token: Some("token".to_owned()),
+            ..PasteMetadata::default()
+        };
+        let burned = PasteMetadata {
+            burn_after_reading: true,
+            ..PasteMetadata::default()
+        };
+        let a = storage.save("aaaa".into(), deletable).await.unwrap();
+        let b = storage.save("bbbb".into(), burned).await.unwrap();
+        assert_eq!(size(&storage), 8);
+
+        assert!(matches!(
+            storage.delete(&a, "wrong").await,
+            Err(DeleteError::InvalidToken)
+        ));
+        assert_eq!(size(&storage), 8);
+
+        storage.delete(&a, "token").await.unwrap();
+        assert_eq!(size(&storage), 4);
+
+        storage.load(&b).await.unwrap();
+        assert_eq!(size(&storage), 0);
+        assert!(matches!(storage.load(&b).await, Err(LoadError::NotFound)));
+    }
+}
@@ -0,0 +1,440 @@
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    io::{self, Cursor},
+    net::IpAddr,
+    ops::Deref,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+mod compressed;
+mod encrypted;
+mod filesystem;
+mod memory;
+mod s3;
+mod sqlite;
+
+pub(crate) use self::compressed::decode;
+pub use self::compressed::CompressedStorage;
+pub use self::encrypted::{EncryptedStorage, EncryptionKeys};
+pub use self::filesystem::FilesystemStorage;
+pub use self::memory::MemoryStorage;
+pub use self::s3::{S3Config, S3Error, S3Storage};
+pub use self::sqlite::SqliteStorage;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SaveError {
+    /// Reading the contents failed, e.g. because the upload was aborted or invalid.
+    #[error("failed to read paste")]
+    Read(#[source] io::Error),
+
+    #[error("failed to save paste")]
+    Failed,
+
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum LoadError {
+    #[error("unknown paste id")]
+    NotFound,
+
+    #[error("paste expired")]
+    Expired,
+
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DeleteError {
+    #[error("unknown paste id")]
+    NotFound,
+
+    #[error("invalid deletion token")]
+    InvalidToken,
+
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PurgeError {
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+}
+
+#[derive(Debug, Clone)]
+pub struct PasteId(String);
+
+impl PasteId {
+    pub fn new(id: String) -> Result<Self, String> {
+        if id
+            .bytes()
+            .all(|b| matches!(b, b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9'))
+        {
+            Ok(Self(id))
+        } else {
+            Err(id)
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for PasteId {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl fmt::Display for PasteId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<PasteId> for String {
+    fn from(id: PasteId) -> Self {
+        id.0
+    }
+}
+
+impl<'de> serde::de::Deserialize<'de> for PasteId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::{Error, Unexpected};
+
+        let id = String::deserialize(deserializer)?;
+
+        PasteId::new(id)
+            .map_err(|id| Error::invalid_value(Unexpected::Str(&id), &"a valid paste id"))
+    }
+}
+
+/// Compression of a stored paste, the names match the HTTP content codings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Gzip,
+    Zstd,
+}
+
+impl Encoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip" => Ok(Self::Gzip),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(format!("unknown encoding '{s}', expected 'gzip' or 'zstd'")),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Information about a paste, persisted alongside its contents.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasteMetadata {
+    /// Original file name of the upload.
+    pub file_name: Option<String>,
+    /// Extension used to highlight the paste when none is requested.
+    pub extension: Option<String>,
+    pub content_type: Option<String>,
+    /// Unix timestamp (seconds) of the upload.
+    pub created_at: u64,
+    /// Size of the paste in bytes, before compression.
+    pub size: u64,
+    pub uploader: Option<IpAddr>,
+    /// Unix timestamp (seconds) after which the paste is no longer served.
+    pub expires_at: Option<u64>,
+    /// Removes the paste after it has been loaded once.
+    pub burn_after_reading: bool,
+    /// Secret required to delete the paste, pastes without a token cannot be deleted.
+    pub delete_token: Option<String>,
+    /// Compression of the stored contents, `None` if stored as is.
+    pub encoding: Option<Encoding>,
+    /// Contents are encrypted, see [`EncryptedStorage`].
+    pub encrypted: bool,
+    /// Contents were encrypted by the uploader, the server never has the key.
+    pub client_encrypted: bool,
+    /// Hex encoded SHA-256 of the stored contents.
+    pub digest: Option<String>,
+    /// Hex encoded SHA-256 of the original contents, before compression or encryption.
+    pub content_digest: Option<String>,
+    /// Files of a paste holding several files, stored one after another.
+    /// Empty for pastes of a single file.
+    pub files: Vec<PasteFile>,
+    /// ID of the original paste this one is a revision of, see [`Storage::add_revision`].
+    pub revision_of: Option<String>,
+}
+
+impl PasteMetadata {
+    pub fn is_expired(&self, now: u64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+    }
+
+    /// Whether `token` is the deletion token of the paste, compared in constant time.
+    pub fn is_delete_token(&self, token: &str) -> bool {
+        match &self.delete_token {
+            Some(delete_token) => delete_token.as_bytes().ct_eq(token.as_bytes()).into(),
+            None => false,
+        }
+    }
+
+    /// Sets the files of the paste, a single file stays an ordinary paste.
+    ///
+    /// A paste of several files has no file name or extension of its own.
+    pub fn set_files(&mut self, files: Vec<PasteFile>) {
+        if files.len() > 1 {
+            self.file_name = None;
+            self.extension = None;
+            self.files = files;
+        }
+    }
+}
+
+/// A file of a paste holding several files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasteFile {
+    /// Name of the file, unique within the paste.
+    pub name: String,
+    /// Extension used to highlight the file.
+    pub extension: Option<String>,
+    pub content_type: String,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Hex encoded SHA-256 of the file.
+    pub digest: String,
+}
+
+/// Contents of a paste which is being saved.
+///
+/// Counts and hashes the bytes read from the original contents, even if they are transformed
+/// on the way to the storage, e.g. compressed.
+pub struct PasteData<'a> {
+    reader: Box<dyn AsyncRead + Send + Unpin + 'a>,
+    original: Arc<Mutex<Original>>,
+    /// Files found while reading the contents, see [`PasteMetadata::files`].
+    files: Option<Arc<Mutex<Vec<PasteFile>>>>,
+}
+
+#[derive(Default)]
+struct Original {
+    size: u64,
+    hasher: Sha256,
+}
+
+impl<'a> PasteData<'a> {
+    pub fn new(reader: impl AsyncRead + Send + Unpin + 'a) -> Self {
+        let original = Arc::default();
+        let reader = Counted {
+            reader,
+            original: Arc::clone(&original),
+        };
+
+        Self {
+            reader: Box::new(reader),
+            original,
+            files: None,
+        }
+    }
+
+    /// Contents holding several files, `files` is filled by the reader while it is read.
+    pub fn with_files(mut self, files: Arc<Mutex<Vec<PasteFile>>>) -> Self {
+        self.files = Some(files);
+        self
+    }
+
+    /// Transforms the contents, the size and digest keep covering the original contents.
+    pub fn map<R, F>(self, f: F) -> Self
+    where
+        R: AsyncRead + Send + Unpin + 'a,
+        F: FnOnce(Box<dyn AsyncRead + Send + Unpin + 'a>) -> R,
+    {
+        Self {
+            reader: Box::new(f(self.reader)),
+            original: self.original,
+            files: self.files,
+        }
+    }
+
+    /// Amount of bytes read from the original contents so far.
+    pub fn size(&self) -> u64 {
+        self.original.lock().unwrap().size
+    }
+
+    /// Hex encoded SHA-256 of the original contents read so far.
+    pub fn digest(&self) -> String {
+        hex::encode(self.original.lock().unwrap().hasher.clone().finalize())
+    }
+
+    /// Sets the size, content digest and files in `metadata`, once all contents were read.
+    pub fn finish(&self, metadata: &mut PasteMetadata) {
+        self.progress().finish(metadata);
+    }
+
+    /// Tracks the contents after they have been passed on, e.g. to a [`Storage`].
+    pub fn progress(&self) -> PasteProgress {
+        PasteProgress {
+            original: Arc::clone(&self.original),
+            files: self.files.clone(),
+        }
+    }
+
+    /// Reads the remaining contents into memory.
+    pub async fn read_to_bytes(&mut self) -> Result<Bytes, SaveError> {
+        let mut data = Vec::new();
+        self.read_to_end(&mut data).await.map_err(SaveError::Read)?;
+        Ok(data.into())
+    }
+}
+
+/// Size, digest and files of the contents of a [`PasteData`] read so far.
+pub struct PasteProgress {
+    original: Arc<Mutex<Original>>,
+    files: Option<Arc<Mutex<Vec<PasteFile>>>>,
+}
+
+impl PasteProgress {
+    /// Sets the size, content digest and files in `metadata`, once all contents were read.
+    pub fn finish(&self, metadata: &mut PasteMetadata) {
+        let original = self.original.lock().unwrap();
+        metadata.size = original.size;
+        metadata.content_digest = Some(hex::encode(original.hasher.clone().finalize()));
+        if let Some(files) = &self.files {
+            metadata.set_files(files.lock().unwrap().clone());
+        }
+    }
+}
+
+impl From<Bytes> for PasteData<'static> {
+    fn from(data: Bytes) -> Self {
+        Self::new(Cursor::new(data))
+    }
+}
+
+impl AsyncRead for PasteData<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.reader).poll_read(cx, buf)
+    }
+}
+
+struct Counted<R> {
+    reader: R,
+    original: Arc<Mutex<Original>>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Counted<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.reader).poll_read(cx, buf);
+
+        let read = &buf.filled()[before..];
+        let mut original = self.original.lock().unwrap();
+        original.size += read.len() as u64;
+        original.hasher.update(read);
+
+        result
+    }
+}
+
+/// A paste loaded from a [`Storage`].
+pub struct Paste {
+    pub data: Box<dyn AsyncRead + Send + Sync + Unpin>,
+    pub metadata: PasteMetadata,
+}
+
+#[async_trait::async_trait]
+pub trait Storage {
+    /// Saves a paste held in memory, like [`Storage::save_stream`].
+    async fn save(&self, data: Bytes, metadata: PasteMetadata) -> Result<PasteId, SaveError> {
+        self.save_stream(data.into(), metadata).await
+    }
+    /// Saves a paste while reading it from `data`, `metadata` is completed with
+    /// [`PasteData::finish`] once all of `data` has been read.
+    ///
+    /// Read errors are returned as [`SaveError::Read`].
+    async fn save_stream(
+        &self,
+        data: PasteData<'_>,
+        metadata: PasteMetadata,
+    ) -> Result<PasteId, SaveError>;
+    /// Loads a paste, pastes marked as burn after reading are removed atomically,
+    /// only a single caller will ever successfully load them.
+    async fn load(&self, id: &PasteId) -> Result<Paste, LoadError>;
+    /// Like [`Storage::load`], but may keep the contents in one of the `accepted` encodings,
+    /// the encoding of the returned contents is in [`PasteMetadata::encoding`].
+    async fn load_encoded(&self, id: &PasteId, accepted: &[Encoding]) -> Result<Paste, LoadError> {
+        let _ = accepted;
+        self.load(id).await
+    }
+    /// Loads only the metadata of a paste, never removes the paste.
+    async fn metadata(&self, id: &PasteId) -> Result<PasteMetadata, LoadError>;
+    /// Links the paste `revision` to the paste `id` as its newest revision, returns the
+    /// number of the revision. The paste itself is revision 1.
+    async fn add_revision(&self, id: &PasteId, revision: &PasteId) -> Result<u32, SaveError>;
+    /// Revisions linked to the paste `id`, oldest first, starting with revision 2.
+    ///
+    /// The links are removed with the paste, the revisions are independent pastes
+    /// and may be gone before it.
+    async fn revisions(&self, id: &PasteId) -> Result<Vec<PasteId>, LoadError>;
+    /// Deletes a paste if `token` matches the token it was saved with.
+    async fn delete(&self, id: &PasteId, token: &str) -> Result<(), DeleteError>;
+    /// Removes all expired pastes, returns the amount of removed pastes.
+    async fn purge_expired(&self) -> Result<usize, PurgeError>;
+}
+
+/// Periodically purges expired pastes from `storage`, runs forever.
+pub async fn reap_expired(storage: Arc<dyn Storage + Send + Sync>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        match storage.purge_expired().await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("purged {count} expired pastes"),
+            Err(err) => tracing::error!("failed to purge expired pastes: {err}"),
+        }
+    }
+}
@@ -0,0 +1,458 @@
+use axum::Extension;
+use rusqlite::{Connection, ErrorCode, OptionalExtension, TransactionBehavior};
+use std::{
+    io::{self, Cursor},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use super::{
+    DeleteError, LoadError, Paste, PasteData, PasteId, PasteMetadata, PurgeError, SaveError,
+    Storage,
+};
+use crate::{utils::unix_now, IdGen, StorageExtension};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS pastes (
+    id TEXT PRIMARY KEY NOT NULL,
+    data BLOB NOT NULL,
+    metadata TEXT NOT NULL,
+    expires_at INTEGER
+);
+CREATE INDEX IF NOT EXISTS pastes_expires_at ON pastes (expires_at) WHERE expires_at IS NOT NULL;
+CREATE TABLE IF NOT EXISTS revisions (
+    id TEXT NOT NULL,
+    revision TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS revisions_id ON revisions (id);
+";
+
+/// Stores pastes and their metadata in a single SQLite database.
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+    id_gen: Arc<dyn IdGen + Sync + Send>,
+}
+
+impl SqliteStorage {
+    pub fn new(
+        path: impl AsRef<Path>,
+        id_gen: impl IdGen + Send + Sync + 'static,
+    ) -> Result<Self, rusqlite::Error> {
+        let connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            id_gen: Arc::new(id_gen),
+        })
+    }
+
+    pub fn into_extension(self) -> StorageExtension {
+        Extension(Arc::new(self))
+    }
+
+    /// Runs a blocking database operation on the blocking thread pool.
+    async fn with_connection<T, F>(&self, f: F) -> io::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = Arc::clone(&self.connection);
+
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .map_err(|_| io::Error::other("database connection poisoned"))?;
+            f(&mut connection).map_err(io::Error::other)
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+}
+
+fn is_constraint_violation(err: &rusqlite::Error) -> bool {
+    matches!(err, rusqlite::Error::SqliteFailure(e, _) if e.code == ErrorCode::ConstraintViolation)
+}
+
+fn decode_metadata(metadata: String) -> rusqlite::Result<PasteMetadata> {
+    serde_json::from_str(&metadata).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+    })
+}
+
+#[async_trait::async_trait]
+impl Storage for SqliteStorage {
+    #[tracing::instrument(err, skip(self, paste, metadata))]
+    async fn save_stream(
+        &self,
+        mut paste: PasteData<'_>,
+        mut metadata: PasteMetadata,
+    ) -> Result<PasteId, SaveError> {
+        let data = paste.read_to_bytes().await?;
+        paste.finish(&mut metadata);
+
+        let encoded = serde_json::to_string(&metadata).map_err(|_| SaveError::Failed)?;
+        let id_gen = Arc::clone(&self.id_gen);
+
+        let id = self
+            .with_connection(move |connection| {
+                for attempt in 0..10 {
+                    let id = id_gen.next_id(attempt);
+
+                    // ID collisions are caught by the primary key
+                    let result = connection.execute(
+                        "INSERT INTO pastes (id, data, metadata, expires_at) VALUES (?1, ?2, ?3, ?4)",
+                        (&id, data.as_ref(), &encoded, metadata.expires_at),
+                    );
+
+                    match result {
+                        Ok(_) => return Ok(Some(id)),
+                        Err(e) if is_constraint_violation(&e) => continue,
+                        Err(e) => return Err(e),
+                    }
+                }
+
+                Ok(None)
+            })
+            .await
+            .map_err(|err| {
+                tracing::error!("failed to save paste: {err}");
+                SaveError::Failed
+            })?
+            .ok_or(SaveError::Failed)?;
+
+        tracing::info!("saved {id}");
+
+        Ok(PasteId(id))
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn load(&self, id: &PasteId) -> Result<Paste, LoadError> {
+        let key = id.to_string();
+
+        let paste = self
+            .with_connection(move |connection| {
+                let transaction =
+                    connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+                let paste = transaction
+                    .query_row(
+                        "SELECT data, metadata FROM pastes WHERE id = ?1",
+                        [&key],
+                        |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, String>(1)?)),
+                    )
+                    .optional()?;
+
+                let (data, metadata) = match paste {
+                    Some((data, metadata)) => (data, decode_metadata(metadata)?),
+                    None => return Ok(None),
+                };
+
+                // Selecting and deleting in one transaction lets only a single reader succeed.
+                if metadata.burn_after_reading && !metadata.is_expired(unix_now()) {
+                    transaction.execute("DELETE FROM pastes WHERE id = ?1", [&key])?;
+                    transaction.execute("DELETE FROM revisions WHERE id = ?1", [&key])?;
+                }
+                transaction.commit()?;
+
+                Ok(Some((data, metadata)))
+            })
+            .await?;
+
+        let (data, metadata) = paste.ok_or(LoadError::NotFound)?;
+        if metadata.is_expired(unix_now()) {
+            return Err(LoadError::Expired);
+        }
+
+        tracing::info!("loaded paste {id}");
+
+        Ok(Paste {
+            data: Box::new(Cursor::new(data)),
+            metadata,
+        })
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn metadata(&self, id: &PasteId) -> Result<PasteMetadata, LoadError> {
+        let key = id.to_string();
+
+        let metadata = self
+            .with_connection(move |connection| {
+                connection
+                    .query_row("SELECT metadata FROM pastes WHERE id = ?1", [&key], |row| {
+                        row.get::<_, String>(0)
+                    })
+                    .optional()?
+                    .map(decode_metadata)
+                    .transpose()
+            })
+            .await?
+            .ok_or(LoadError::NotFound)?;
+
+        if metadata.is_expired(unix_now()) {
+            return Err(LoadError::Expired);
+        }
+
+        Ok(metadata)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn add_revision(&self, id: &PasteId, revision: &PasteId) -> Result<u32, SaveError> {
+        let key = id.to_string();
+        let revision = revision.to_string();
+
+        let count = self
+            .with_connection(move |connection| {
+                let transaction =
+                    connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+                transaction.execute(
+                    "INSERT INTO revisions (id, revision) VALUES (?1, ?2)",
+                    [&key, &revision],
+                )?;
+                let count = transaction.query_row(
+                    "SELECT COUNT(*) FROM revisions WHERE id = ?1",
+                    [&key],
+                    |row| row.get::<_, u32>(0),
+                )?;
+                transaction.commit()?;
+
+                Ok(count)
+            })
+            .await?;
+
+        Ok(count + 1)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn revisions(&self, id: &PasteId) -> Result<Vec<PasteId>, LoadError> {
+        let key = id.to_string();
+
+        let revisions = self
+            .with_connection(move |connection| {
+                connection
+                    .prepare("SELECT revision FROM revisions WHERE id = ?1 ORDER BY rowid")?
+                    .query_map([&key], |row| row.get::<_, String>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .await?;
+
+        Ok(revisions.into_iter().map(PasteId).collect())
+    }
+
+    #[tracing::instrument(err, skip(self, token))]
+    async fn delete(&self, id: &PasteId, token: &str) -> Result<(), DeleteError> {
+        let key = id.to_string();
+        let token = token.to_owned();
+
+        let deleted = self
+            .with_connection(move |connection| {
+                let transaction =
+                    connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+                let metadata = transaction
+                    .query_row("SELECT metadata FROM pastes WHERE id = ?1", [&key], |row| {
+                        row.get::<_, String>(0)
+                    })
+                    .optional()?
+                    .map(decode_metadata)
+                    .transpose()?;
+
+                let deleted = match metadata {
+                    Some(metadata) if metadata.is_delete_token(&token) => {
+                        transaction.execute("DELETE FROM pastes WHERE id = ?1", [&key])?;
+                        transaction.execute("DELETE FROM revisions WHERE id = ?1", [&key])?;
+                        Ok(())
+                    }
+                    Some(_) => Err(DeleteError::InvalidToken),
+                    None => Err(DeleteError::NotFound),
+                };
+                transaction.commit()?;
+
+                Ok(deleted)
+            })
+            .await?;
+
+        deleted?;
+
+        tracing::info!("deleted paste {id}");
+
+        Ok(())
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn purge_expired(&self) -> Result<usize, PurgeError> {
+        let now = unix_now();
+
+        let count = self
+            .with_connection(move |connection| {
+                let count =
+                    connection.execute("DELETE FROM pastes WHERE expires_at <= ?1", [now])?;
+                connection.execute(
+                    "DELETE FROM revisions WHERE id NOT IN (SELECT id FROM pastes)",
+                    [],
+                )?;
+                Ok(count)
+            })
+            .await?;
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RandomIdGen;
+    use tokio::io::AsyncReadExt;
+
+    fn storage() -> SqliteStorage {
+        SqliteStorage::new(":memory:", RandomIdGen::new(8)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn save_sets_size_and_digest() {
+        let storage = storage();
+
+        let id = storage
+            .save("hello world".into(), PasteMetadata::default())
+            .await
+            .unwrap();
+
+        let metadata = storage.metadata(&id).await.unwrap();
+        assert_eq!(metadata.size, 11);
+        assert_eq!(
+            metadata.content_digest.as_deref(),
+            Some("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9")
+        );
+    }
+
+    async fn read(storage: &SqliteStorage, id: &PasteId) -> Result<String, LoadError> {
+        let mut paste = storage.load(id).await?;
+        let mut data = String::new();
+        paste.data.read_to_string(&mut data).await.unwrap();
+        Ok(data)
+    }
+
+    #[tokio::test]
+    async fn save_and_load() {
+        let storage = storage();
+
+        let metadata = PasteMetadata {
+            file_name: Some("main.rs".to_owned()),
+            ..PasteMetadata::default()
+        };
+        let id = storage.save("fn main() {}".into(), metadata).await.unwrap();
+
+        assert_eq!(read(&storage, &id).await.unwrap(), "fn main() {}");
+        // loading does not remove it
+        assert_eq!(read(&storage, &id).await.unwrap(), "fn main() {}");
+
+        let metadata = storage.metadata(&id).await.unwrap();
+        assert_eq!(metadata.file_name.as_deref(), Some("main.rs"));
+
+        let unknown = PasteId::new("unknown".to_owned()).unwrap();
+        assert!(matches!(
+            read(&storage, &unknown).await,
+            Err(LoadError::NotFound)
+        ));
+        assert!(matches!(
+            storage.metadata(&unknown).await,
+            Err(LoadError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn burn_after_reading() {
+        let storage = storage();
+
+        let metadata = PasteMetadata {
+            burn_after_reading: true,
+            ..PasteMetadata::default()
+        };
+        let id = storage.save("secret".into(), metadata).await.unwrap();
+
+        // the metadata alone does not burn it
+        assert!(storage.metadata(&id).await.unwrap().burn_after_reading);
+
+        assert_eq!(read(&storage, &id).await.unwrap(), "secret");
+        assert!(matches!(
+            read(&storage, &id).await,
+            Err(LoadError::NotFound)
+        ));
+        assert!(matches!(
+            storage.metadata(&id).await,
+            Err(LoadError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn delete() {
+        let storage = storage();
+
+        let metadata = PasteMetadata {
+            delete_token: Some("token".to_owned()),
+            ..PasteMetadata::default()
+        };
+        let id = storage.save("a".into(), metadata).await.unwrap();
+        let revision = storage
+            .save("b".into(), PasteMetadata::default())
+            .await
+            .unwrap();
+        assert_eq!(storage.add_revision(&id, &revision).await.unwrap(), 2);
+
+        assert!(matches!(
+            storage.delete(&id, "wrong").await,
+            Err(DeleteError::InvalidToken)
+        ));
+        assert_eq!(read(&storage, &id).await.unwrap(), "a");
+
+        storage.delete(&id, "token").await.unwrap();
+        assert!(matches!(
+            read(&storage, &id).await,
+            Err(LoadError::NotFound)
+        ));
+        assert!(storage.revisions(&id).await.unwrap().is_empty());
+        // revisions are independent pastes
+        assert_eq!(read(&storage, &revision).await.unwrap(), "b");
+
+        assert!(matches!(
+            storage.delete(&id, "token").await,
+            Err(DeleteError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn purge_expired() {
+        let storage = storage();
+
+        let expired = PasteMetadata {
+            expires_at: Some(unix_now() - 1),
+            ..PasteMetadata::default()
+        };
+        let expiring = PasteMetadata {
+            expires_at: Some(unix_now() + 3600),
+            ..PasteMetadata::default()
+        };
+        let a = storage.save("a".into(), expired).await.unwrap();
+        let b = storage.save("b".into(), expiring).await.unwrap();
+        let c = storage
+            .save("c".into(), PasteMetadata::default())
+            .await
+            .unwrap();
+
+        assert!(matches!(read(&storage, &a).await, Err(LoadError::Expired)));
+        assert!(matches!(
+            storage.metadata(&a).await,
+            Err(LoadError::Expired)
+        ));
+
+        assert_eq!(storage.purge_expired().await.unwrap(), 1);
+        assert_eq!(storage.purge_expired().await.unwrap(), 0);
+
+        assert!(matches!(read(&storage, &a).await, Err(LoadError::NotFound)));
+        assert_eq!(read(&storage, &b).await.unwrap(), "b");
+        assert_eq!(read(&storage, &c).await.unwrap(), "c");
+    }
+}
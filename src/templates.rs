@@ -1,8 +1,16 @@
 use askama::Template;
 
+/// The upload form, prefilled to edit or fork a paste.
 #[derive(Template, Default)]
 #[template(path = "index.html")]
-pub struct Index;
+pub struct Index<'a> {
+    /// Where the form is posted to, the page itself if empty.
+    pub action: &'a str,
+    pub content: &'a str,
+    pub lang: &'a str,
+    /// Deletion token of the paste being edited.
+    pub token: &'a str,
+}
 
 #[derive(Template, Default)]
 #[template(path = "view.html")]
@@ -11,3 +19,81 @@ pub struct View<'a> {
     pub source: &'a [&'a str],
     pub is_escaped: bool,
 }
+
+/// All files of a paste holding several files.
+#[derive(Template)]
+#[template(path = "bundle.html")]
+pub struct Bundle<'a> {
+    pub css: &'a str,
+    pub id: &'a str,
+    pub files: &'a [BundleFile<'a>],
+}
+
+pub struct BundleFile<'a> {
+    pub name: &'a str,
+    /// Lines of a text file, empty for images.
+    pub source: Vec<String>,
+    pub is_escaped: bool,
+    pub is_image: bool,
+}
+
+/// All revisions of a paste, oldest first.
+#[derive(Template)]
+#[template(path = "history.html")]
+pub struct History<'a> {
+    pub id: &'a str,
+    pub revisions: &'a [Revision],
+}
+
+pub struct Revision {
+    pub number: usize,
+    pub url: String,
+    /// Changes since the previous revision, none for the original paste.
+    pub diff: Option<String>,
+    /// HTTP date, empty for pastes saved without one.
+    pub created_at: String,
+    pub size: u64,
+}
+
+/// Line diff of two pastes, unified or side by side.
+#[derive(Template)]
+#[template(path = "diff.html")]
+pub struct Diff<'a> {
+    pub css: &'a str,
+    pub a: &'a str,
+    pub b: &'a str,
+    pub split: bool,
+    pub rows: &'a [DiffRow<'a>],
+    pub is_escaped: bool,
+}
+
+/// A line of either paste or both, unchanged lines have both.
+///
+/// Unified diffs only pair up unchanged lines, side by side changed lines are paired too.
+pub struct DiffRow<'a> {
+    pub old: Option<DiffLine<'a>>,
+    pub new: Option<DiffLine<'a>>,
+    pub changed: bool,
+}
+
+impl DiffRow<'_> {
+    /// Source of the new line, or of the old one if it was removed.
+    pub fn source(&self) -> &str {
+        self.new
+            .as_ref()
+            .or(self.old.as_ref())
+            .map_or("", |line| line.source)
+    }
+}
+
+pub struct DiffLine<'a> {
+    pub number: usize,
+    pub source: &'a str,
+}
+
+#[derive(Template, Default)]
+#[template(path = "decrypt.html")]
+pub struct Decrypt<'a> {
+    /// Base64 encoded ciphertext.
+    pub data: &'a str,
+}
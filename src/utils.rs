@@ -1,43 +1,326 @@
-use axum::extract::{FromRequest, RequestParts};
+use axum::{
+    body::HttpBody,
+    extract::{BodyStream, ConnectInfo, FromRequest, RequestParts},
+    BoxError,
+};
+use bytes::Bytes;
 use hyper::{header, HeaderMap};
 use infer::MatcherType;
 use serde::{de::value::StrDeserializer, Deserialize};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
 
+use crate::Config;
+
+/// Kind of a file, an image or text.
 #[derive(Copy, Clone)]
-pub enum File<'a> {
-    Binary(&'a [u8], infer::Type),
-    Text(&'a str, Option<infer::Type>),
+pub enum FileKind {
+    Binary(infer::Type),
+    Text(Option<infer::Type>),
 }
 
-impl<'a> File<'a> {
-    pub fn infer(data: &'a [u8]) -> crate::Result<Self> {
-        let ft = if let Some(ft) = infer::get(data) {
-            if !ft.mime_type().starts_with("text/") {
+impl FileKind {
+    /// Detects the kind of a file from its start, text is not validated.
+    pub fn sniff(data: &[u8]) -> crate::Result<Self> {
+        match infer::get(data) {
+            Some(ft) if !ft.mime_type().starts_with("text/") => {
                 if !matches!(ft.matcher_type(), MatcherType::Image) {
                     return Err(crate::Error::UnsupportedFile(ft.mime_type()));
                 }
 
-                return Ok(Self::Binary(data, ft));
+                Ok(Self::Binary(ft))
             }
+            ft => Ok(Self::Text(ft)),
+        }
+    }
 
-            Some(ft)
-        } else {
-            None
-        };
+    /// Detects the kind of a whole file, text has to be valid UTF-8 and not only whitespace.
+    pub fn infer(data: &[u8]) -> crate::Result<Self> {
+        let kind = Self::sniff(data)?;
+        if let Self::Text(_) = kind {
+            let text = std::str::from_utf8(data).map_err(|_| crate::Error::NotUtf8)?;
+            if text.trim().is_empty() {
+                return Err(crate::Error::Empty);
+            }
+        }
+        Ok(kind)
+    }
+
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Self::Binary(ft) => Some(ft.extension()),
+            Self::Text(ft) => ft.map(|ft| ft.extension()),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Binary(ft) => ft.mime_type(),
+            Self::Text(_) => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+/// Validates UTF-8 which arrives in chunks, sequences may be split between chunks.
+#[derive(Default)]
+pub struct Utf8Validator {
+    /// Start of a sequence at the end of the previous chunk.
+    pending: Vec<u8>,
+    /// Anything but whitespace was seen.
+    has_content: bool,
+}
+
+impl Utf8Validator {
+    pub fn update(&mut self, mut data: &[u8]) -> crate::Result<()> {
+        if !self.pending.is_empty() {
+            // complete the pending sequence with the start of the new chunk
+            let take = data.len().min(4 - self.pending.len());
+            let mut sequence = std::mem::take(&mut self.pending);
+            sequence.extend_from_slice(&data[..take]);
+
+            let valid = match std::str::from_utf8(&sequence) {
+                Ok(_) => sequence.len(),
+                Err(e) if e.valid_up_to() > 0 => e.valid_up_to(),
+                Err(e) if e.error_len().is_none() && take == data.len() => {
+                    self.pending = sequence;
+                    return Ok(());
+                }
+                Err(_) => return Err(crate::Error::NotUtf8),
+            };
 
-        let data = std::str::from_utf8(data).map_err(|_| crate::Error::NotUtf8)?;
+            self.check_content(std::str::from_utf8(&sequence[..valid]).unwrap());
+            data = &data[valid - (sequence.len() - take)..];
+        }
+
+        match std::str::from_utf8(data) {
+            Ok(s) => self.check_content(s),
+            Err(e) if e.error_len().is_none() => {
+                let (valid, pending) = data.split_at(e.valid_up_to());
+                self.check_content(std::str::from_utf8(valid).unwrap());
+                self.pending = pending.to_vec();
+            }
+            Err(_) => return Err(crate::Error::NotUtf8),
+        }
+
+        Ok(())
+    }
 
-        if data.trim().is_empty() {
+    /// Validates the end of the text, which must not be empty.
+    pub fn finish(&self) -> crate::Result<()> {
+        if !self.pending.is_empty() {
+            return Err(crate::Error::NotUtf8);
+        }
+        if !self.has_content {
             return Err(crate::Error::Empty);
         }
+        Ok(())
+    }
 
-        Ok(Self::Text(data, ft))
+    fn check_content(&mut self, s: &str) {
+        self.has_content = self.has_content || !s.trim().is_empty();
     }
+}
 
-    pub fn extension(&self) -> Option<&'static str> {
-        match self {
-            Self::Binary(_, ft) => Some(ft.extension()),
-            Self::Text(_, ft) => ft.map(|ft| ft.extension()),
+/// Reads an upload, the size limit and the sniffed file kind are enforced while reading.
+///
+/// Errors of the upload itself are returned as [`io::ErrorKind::InvalidData`]
+/// wrapping a [`crate::Error`].
+pub struct UploadReader<R> {
+    reader: R,
+    /// Start of the upload, read ahead to sniff its kind.
+    head: Bytes,
+    kind: Option<FileKind>,
+    text: Option<Utf8Validator>,
+    read: u64,
+    limit: u64,
+}
+
+impl<R: AsyncRead + Unpin> UploadReader<R> {
+    /// Amount of bytes used to sniff the kind of an upload.
+    const SNIFF_LEN: u64 = 8 * 1024;
+
+    /// Sniffs the kind of an upload from its start, `None` if it is empty.
+    ///
+    /// The size `limit` depends on the sniffed kind.
+    pub async fn new(
+        reader: R,
+        limit: impl FnOnce(&FileKind) -> u64,
+    ) -> crate::Result<Option<Self>> {
+        let mut upload = match Self::opaque(reader, u64::MAX).await? {
+            Some(upload) => upload,
+            None => return Ok(None),
+        };
+
+        let kind = FileKind::sniff(&upload.head)?;
+        upload.limit = limit(&kind);
+        if upload.read > upload.limit {
+            return Err(crate::Error::PayloadTooLarge(upload.limit));
+        }
+
+        if let FileKind::Text(_) = kind {
+            let mut text = Utf8Validator::default();
+            text.update(&upload.head)?;
+            upload.text = Some(text);
+        }
+        upload.kind = Some(kind);
+
+        Ok(Some(upload))
+    }
+
+    /// Reads an upload as is, only the size limit is enforced.
+    pub async fn opaque(mut reader: R, limit: u64) -> crate::Result<Option<Self>> {
+        let mut head = Vec::new();
+        (&mut reader)
+            .take(Self::SNIFF_LEN)
+            .read_to_end(&mut head)
+            .await
+            .map_err(|_| crate::Error::BadRequest)?;
+
+        if head.is_empty() {
+            return Ok(None);
+        }
+        if head.len() as u64 > limit {
+            return Err(crate::Error::PayloadTooLarge(limit));
+        }
+
+        Ok(Some(Self {
+            reader,
+            read: head.len() as u64,
+            head: head.into(),
+            kind: None,
+            text: None,
+            limit,
+        }))
+    }
+
+    /// Kind of the upload, `None` if it is read as is.
+    pub fn kind(&self) -> Option<FileKind> {
+        self.kind
+    }
+
+    /// Start of the upload which is read ahead, up to 8 KiB until it is read.
+    pub fn head(&self) -> &[u8] {
+        &self.head
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for UploadReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+
+        if !this.head.is_empty() {
+            let len = this.head.len().min(buf.remaining());
+            buf.put_slice(&this.head.split_to(len));
+            return Poll::Ready(Ok(()));
+        }
+
+        let before = buf.filled().len();
+        futures_util::ready!(Pin::new(&mut this.reader).poll_read(cx, buf))?;
+        let data = &buf.filled()[before..];
+
+        let invalid = |err: crate::Error| io::Error::new(io::ErrorKind::InvalidData, err);
+
+        if data.is_empty() {
+            if let Some(text) = &this.text {
+                text.finish().map_err(invalid)?;
+            }
+            return Poll::Ready(Ok(()));
+        }
+
+        this.read += data.len() as u64;
+        let result = if this.read > this.limit {
+            Err(crate::Error::PayloadTooLarge(this.limit))
+        } else if let Some(text) = &mut this.text {
+            text.update(data)
+        } else {
+            Ok(())
+        };
+
+        if let Err(err) = result {
+            // nothing may be read along with an error
+            buf.set_filled(before);
+            return Poll::Ready(Err(invalid(err)));
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Size limit of multipart fields other than files, e.g. `expires` or `token`.
+const MULTIPART_FIELD_LIMIT: u64 = 1024;
+/// Allowance for the boundaries, part headers and options next to the files of a multipart upload.
+const MULTIPART_OVERHEAD: u64 = 64 * 1024;
+
+/// Body of an upload, either a form, JSON for the API or the file itself.
+pub enum UploadBody {
+    Multipart(multer::Multipart<'static>),
+    /// An URL encoded form submitted by a browser.
+    Form(BodyStream),
+    Json(BodyStream),
+    Raw(BodyStream),
+}
+
+#[async_trait::async_trait]
+impl<B> FromRequest<B> for UploadBody
+where
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = crate::Error;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+
+        // `curl --data-binary` sends files as URL encoded forms too, only browsers send
+        // where a form was submitted from
+        let is_browser = [header::ORIGIN, header::REFERER]
+            .iter()
+            .any(|name| req.headers().contains_key(name));
+
+        let body = BodyStream::from_request(req)
+            .await
+            .map_err(|_| crate::Error::BadRequest)?;
+
+        if content_type.starts_with("multipart/form-data") {
+            let boundary =
+                multer::parse_boundary(&content_type).map_err(|_| crate::Error::BadRequest)?;
+            // All files together are limited like a single one, see `bundle_reader`.
+            let config = req.extensions().get::<Arc<Config>>().cloned();
+            let config = config.unwrap_or_default();
+            let limit = config.max_text_size.max(config.max_image_size);
+            let constraints = multer::Constraints::new().size_limit(
+                multer::SizeLimit::new()
+                    .whole_stream(limit + MULTIPART_OVERHEAD)
+                    .per_field(MULTIPART_FIELD_LIMIT)
+                    .for_field("file", limit),
+            );
+
+            Ok(Self::Multipart(multer::Multipart::with_constraints(
+                body,
+                boundary,
+                constraints,
+            )))
+        } else if content_type.starts_with("application/x-www-form-urlencoded") && is_browser {
+            Ok(Self::Form(body))
+        } else if content_type.starts_with("application/json") {
+            Ok(Self::Json(body))
+        } else {
+            Ok(Self::Raw(body))
         }
     }
 }
@@ -69,6 +352,8 @@ where
     }
 }
 
+/// Scheme the client used, taken from the request or, behind one of the
+/// [`Config::trusted_proxies`], from its headers.
 pub struct Protocol(pub String);
 
 #[async_trait::async_trait]
@@ -79,16 +364,18 @@ where
     type Rejection = ();
 
     async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
-        if let Some(protocol) = parse_forwarded_for_protocol(req.headers()) {
-            return Ok(Self(protocol.to_owned()));
-        }
+        let (peer, trusted) = peer(req);
 
-        if let Some(protocol) = req
-            .headers()
-            .get("X-Forwarded-Proto")
-            .and_then(|proto| proto.to_str().ok())
-        {
-            return Ok(Self(protocol.to_owned()));
+        // anyone else can send any headers
+        if peer.is_some_and(|peer| trusted.contains(&peer)) {
+            let forwarded = parse_forwarded(req.headers(), "proto").or_else(|| {
+                req.headers()
+                    .get("X-Forwarded-Proto")
+                    .and_then(|proto| proto.to_str().ok())
+            });
+            if let Some(protocol) = forwarded.filter(|p| matches!(*p, "http" | "https")) {
+                return Ok(Self(protocol.to_owned()));
+            }
         }
 
         if let Some(protocol) = req.uri().scheme_str() {
@@ -99,14 +386,357 @@ where
     }
 }
 
-fn parse_forwarded_for_protocol(headers: &HeaderMap) -> Option<&str> {
+/// Address of the peer of the connection and the proxies trusted to forward requests.
+fn peer<B>(req: &RequestParts<B>) -> (Option<IpAddr>, &[IpAddr]) {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let trusted = match req.extensions().get::<Arc<Config>>() {
+        Some(config) => &config.trusted_proxies[..],
+        None => &[],
+    };
+    (peer, trusted)
+}
+
+/// Address of the client, taken from the connection or, behind one of the
+/// [`Config::trusted_proxies`], from its headers.
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait::async_trait]
+impl<B> FromRequest<B> for ClientIp
+where
+    B: Send,
+{
+    type Rejection = ();
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let (peer, trusted) = peer(req);
+
+        // anyone else can send any headers
+        if !peer.is_some_and(|peer| trusted.contains(&peer)) {
+            return Ok(Self(peer));
+        }
+
+        // Proxies append the address they received the request from, the client is the last
+        // one which was not appended by a trusted proxy.
+        let forwarded = forwarded_for(req.headers());
+        let ip = forwarded
+            .iter()
+            .rev()
+            .find(|ip| !trusted.contains(ip))
+            .or(forwarded.first())
+            .copied()
+            .or(peer);
+
+        Ok(Self(ip))
+    }
+}
+
+/// Addresses of the `for` parameters of the `Forwarded` header, or of `X-Forwarded-For`.
+fn forwarded_for(headers: &HeaderMap) -> Vec<IpAddr> {
+    let values = |name: &'static str| {
+        headers
+            .get_all(name)
+            .into_iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+    };
+
+    let forwarded = values(header::FORWARDED.as_str())
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| value.trim().trim_matches('"'))
+            })
+        })
+        .filter_map(parse_ip)
+        .collect::<Vec<_>>();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    values("x-forwarded-for").filter_map(parse_ip).collect()
+}
+
+/// Parses an address which may be bracketed and may have a port, e.g. `[::1]:8080`.
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim();
+
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            value
+                .strip_prefix('[')
+                .and_then(|value| value.strip_suffix(']'))
+                .and_then(|value| value.parse().ok())
+        })
+}
+
+fn parse_forwarded<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
     let forwarded_values = headers.get(header::FORWARDED)?.to_str().ok()?;
     let first_value = forwarded_values.split(',').next()?;
 
     first_value.split(';').find_map(|pair| {
         let (key, value) = pair.split_once('=')?;
         key.trim()
-            .eq_ignore_ascii_case("proto")
+            .eq_ignore_ascii_case(name)
             .then(|| value.trim().trim_matches('"'))
     })
 }
+
+/// Current time as seconds since the unix epoch.
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    /// A request of `peer` to a server behind the `trusted` proxies.
+    fn request(peer: &str, trusted: &[&str], headers: &[(&str, &str)]) -> RequestParts<()> {
+        let mut request = Request::builder();
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let mut request = request.body(()).unwrap();
+
+        let config = Config {
+            trusted_proxies: trusted.iter().map(|ip| ip.parse().unwrap()).collect(),
+            ..Config::default()
+        };
+        request.extensions_mut().insert(Arc::new(config));
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::new(peer.parse().unwrap(), 1234)));
+        RequestParts::new(request)
+    }
+
+    async fn client_ip(peer: &str, trusted: &[&str], headers: &[(&str, &str)]) -> Option<IpAddr> {
+        let mut request = request(peer, trusted, headers);
+        let ClientIp(ip) = ClientIp::from_request(&mut request).await.unwrap();
+        ip
+    }
+
+    async fn protocol(peer: &str, trusted: &[&str], headers: &[(&str, &str)]) -> String {
+        let mut request = request(peer, trusted, headers);
+        let Protocol(protocol) = Protocol::from_request(&mut request).await.unwrap();
+        protocol
+    }
+
+    #[tokio::test]
+    async fn client_ip_ignores_headers_of_untrusted_peers() {
+        let headers = [("X-Forwarded-For", "1.1.1.1"), ("Forwarded", "for=2.2.2.2")];
+        let ip = client_ip("10.0.0.1", &[], &headers).await;
+        assert_eq!(ip, Some("10.0.0.1".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn client_ip_behind_trusted_proxies() {
+        let trusted = ["10.0.0.1", "10.0.0.2"];
+
+        let headers = [("X-Forwarded-For", "6.6.6.6, 1.1.1.1, 10.0.0.2")];
+        let ip = client_ip("10.0.0.1", &trusted, &headers).await;
+        assert_eq!(ip, Some("1.1.1.1".parse().unwrap()));
+
+        let headers = [("Forwarded", "for=6.6.6.6, for=\"[2001:db8::1]:4711\"")];
+        let ip = client_ip("10.0.0.1", &trusted, &headers).await;
+        assert_eq!(ip, Some("2001:db8::1".parse().unwrap()));
+
+        let ip = client_ip("10.0.0.1", &trusted, &[]).await;
+        assert_eq!(ip, Some("10.0.0.1".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn protocol_ignores_headers_of_untrusted_peers() {
+        for headers in [
+            [("X-Forwarded-Proto", "https")],
+            [("Forwarded", "proto=https")],
+        ] {
+            assert_eq!(protocol("10.0.0.1", &[], &headers).await, "http");
+            assert_eq!(protocol("10.0.0.1", &["10.0.0.2"], &headers).await, "http");
+        }
+    }
+
+    #[tokio::test]
+    async fn protocol_behind_trusted_proxies() {
+        let trusted = ["10.0.0.1"];
+
+        let headers = [("X-Forwarded-Proto", "https")];
+        assert_eq!(protocol("10.0.0.1", &trusted, &headers).await, "https");
+
+        let headers = [
+            ("Forwarded", "for=1.1.1.1;proto=https"),
+            ("X-Forwarded-Proto", "http"),
+        ];
+        assert_eq!(protocol("10.0.0.1", &trusted, &headers).await, "https");
+
+        // only schemes, they end up in URLs
+        let headers = [("X-Forwarded-Proto", "javascript:alert(1)//")];
+        assert_eq!(protocol("10.0.0.1", &trusted, &headers).await, "http");
+
+        assert_eq!(protocol("10.0.0.1", &trusted, &[]).await, "http");
+    }
+
+    fn validate(chunks: &[&[u8]]) -> crate::Result<()> {
+        let mut validator = Utf8Validator::default();
+        for chunk in chunks {
+            validator.update(chunk)?;
+        }
+        validator.finish()
+    }
+
+    #[test]
+    fn utf8_split_sequences() {
+        let text = "aé€😀b".as_bytes();
+
+        // every byte on its own
+        let bytes = text.chunks(1).collect::<Vec<_>>();
+        assert!(validate(&bytes).is_ok());
+
+        for split in 0..text.len() {
+            let (a, b) = text.split_at(split);
+            assert!(validate(&[a, b]).is_ok(), "{split}");
+        }
+
+        // a sequence ending with the chunk
+        assert!(validate(&[&text[..3], &[], &text[3..]]).is_ok());
+    }
+
+    #[test]
+    fn utf8_invalid() {
+        assert!(matches!(validate(&[b"\xff"]), Err(crate::Error::NotUtf8)));
+        assert!(matches!(
+            validate(&[b"a\xc3", b"(b"]),
+            Err(crate::Error::NotUtf8)
+        ));
+        assert!(matches!(
+            validate(&[b"a\xf0\x9f", b"\x98", b"a"]),
+            Err(crate::Error::NotUtf8)
+        ));
+        // truncated at the end
+        assert!(matches!(
+            validate(&[b"a\xe2\x82"]),
+            Err(crate::Error::NotUtf8)
+        ));
+    }
+
+    #[test]
+    fn utf8_whitespace_only() {
+        assert!(matches!(validate(&[]), Err(crate::Error::Empty)));
+        assert!(matches!(
+            validate(&[b" \n", b"\t\r\n"]),
+            Err(crate::Error::Empty)
+        ));
+        // a no-break space split between chunks
+        assert!(matches!(
+            validate(&[b" \xc2", b"\xa0"]),
+            Err(crate::Error::Empty)
+        ));
+        assert!(validate(&[b" \xc2", b"\xa0x"]).is_ok());
+    }
+
+    async fn upload(chunks: Vec<Vec<u8>>, limit: u64) -> crate::Result<Vec<u8>> {
+        let chunks = chunks
+            .into_iter()
+            .map(|c| Ok::<_, io::Error>(Bytes::from(c)));
+        let reader = tokio_util::io::StreamReader::new(futures_util::stream::iter(chunks));
+
+        let mut upload = UploadReader::new(reader, |_| limit)
+            .await?
+            .ok_or(crate::Error::MissingFile)?;
+
+        let mut data = Vec::new();
+        upload.read_to_end(&mut data).await.map_err(|err| {
+            *err.into_inner()
+                .and_then(|err| err.downcast::<crate::Error>().ok())
+                .expect("upload errors wrap a crate::Error")
+        })?;
+        Ok(data)
+    }
+
+    #[tokio::test]
+    async fn upload_text() {
+        // the multi-byte sequence is split at the end of the sniffed head
+        let mut text = vec![b'a'; UploadReader::<&[u8]>::SNIFF_LEN as usize - 1];
+        text.extend_from_slice("éb".as_bytes());
+
+        let chunks = text.chunks(1000).map(<[u8]>::to_vec).collect();
+        assert_eq!(upload(chunks, 1 << 20).await.unwrap(), text);
+    }
+
+    #[tokio::test]
+    async fn upload_invalid_in_later_chunk() {
+        let chunks = vec![vec![b'a'; 10 * 1024], b"a\xff".to_vec()];
+        assert!(matches!(
+            upload(chunks, 1 << 20).await,
+            Err(crate::Error::NotUtf8)
+        ));
+    }
+
+    #[tokio::test]
+    async fn upload_whitespace_only() {
+        let chunks = vec![b"  ".to_vec(), b"\n".to_vec()];
+        assert!(matches!(
+            upload(chunks, 1 << 20).await,
+            Err(crate::Error::Empty)
+        ));
+
+        assert!(matches!(
+            upload(vec![], 1 << 20).await,
+            Err(crate::Error::MissingFile)
+        ));
+    }
+
+    #[tokio::test]
+    async fn upload_limit() {
+        // exceeded only after the sniffed head
+        let chunks = vec![vec![b'a'; 10 * 1024]; 3];
+        assert!(matches!(
+            upload(chunks.clone(), 25 * 1024).await,
+            Err(crate::Error::PayloadTooLarge(limit)) if limit == 25 * 1024
+        ));
+        assert_eq!(upload(chunks, 30 * 1024).await.unwrap().len(), 30 * 1024);
+
+        // exceeded by the head already
+        assert!(matches!(
+            upload(vec![vec![b'a'; 1024]], 100).await,
+            Err(crate::Error::PayloadTooLarge(100))
+        ));
+    }
+
+    #[tokio::test]
+    async fn only_browsers_submit_forms() {
+        let kind = |headers: &[(&str, &str)]| {
+            let mut request = Request::builder()
+                .method("POST")
+                .header("Content-Type", "application/x-www-form-urlencoded");
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            let request = request.body(hyper::Body::from("content=a")).unwrap();
+
+            async move {
+                match UploadBody::from_request(&mut RequestParts::new(request)).await {
+                    Ok(UploadBody::Form(_)) => "form",
+                    Ok(UploadBody::Raw(_)) => "raw",
+                    _ => "other",
+                }
+            }
+        };
+
+        assert_eq!(kind(&[("Origin", "https://example.com")]).await, "form");
+        assert_eq!(kind(&[("Referer", "https://example.com/")]).await, "form");
+        // e.g. `curl --data-binary @file`
+        assert_eq!(kind(&[]).await, "raw");
+    }
+}